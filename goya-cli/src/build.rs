@@ -1,14 +1,21 @@
 use super::path_util::PathUtil;
 use bytesize::ByteSize;
 use console::{style, Emoji};
-use goya::common_prefix_tree::CommonPrefixTree;
-use goya::double_array::DoubleArray;
-use goya_ipadic::ipadic::IPADic;
+use goya::container::{
+    self, Container, Metadata, SECTION_DICTIONARY, SECTION_DOUBLE_ARRAY, SECTION_USER_DICTIONARY,
+    SECTION_WORD_FEATURES,
+};
+use goya::double_array::SystemDoubleArray;
 use goya_ipadic::ipadic_loader::IPADicLoader;
+use goya_ipadic::mecab_binary_loader::MeCabBinaryLoader;
+use goya_ipadic::utf8_dic_loader::Utf8DicLoader;
 use rkyv::ser::{serializers::AllocSerializer, Serializer};
+use rkyv::{AlignedVec, Serialize};
+use std::env;
 use std::error::Error;
 use std::fs;
-use std::time::Instant;
+use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 const LOOKING_GLASS: Emoji = Emoji("🔍", "");
 const PAPER: Emoji = Emoji("📃", "");
@@ -16,7 +23,33 @@ const CLIP: Emoji = Emoji("🔗", "");
 const SPARKLE: Emoji = Emoji("✨", "");
 const TRUCK: Emoji = Emoji("🚚", "");
 
-pub fn build(src_dir: &str, dist_dir: &str) -> Result<(), Box<dyn Error>> {
+#[allow(clippy::enum_variant_names)]
+pub enum DictionaryFormat {
+    IPADic,
+    /// The format of IPADIC in UTF-8, e.g. UniDic and mecab-ko-dic
+    Utf8,
+    /// Binary dictionaries compiled by mecab-dict-index
+    MeCab,
+}
+impl FromStr for DictionaryFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipadic" => Ok(DictionaryFormat::IPADic),
+            "utf8" | "unidic" | "ko-dic" => Ok(DictionaryFormat::Utf8),
+            "mecab" => Ok(DictionaryFormat::MeCab),
+            _ => Err("no match"),
+        }
+    }
+}
+
+pub fn build(
+    src_dir: &str,
+    dist_dir: &str,
+    format: DictionaryFormat,
+    user_dict: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
     PathUtil::from(dist_dir.to_string());
     let timer = Instant::now();
     eprintln!(
@@ -25,28 +58,34 @@ pub fn build(src_dir: &str, dist_dir: &str) -> Result<(), Box<dyn Error>> {
         LOOKING_GLASS
     );
     let loader = IPADicLoader {};
-    let mut loaded = loader.load(src_dir)?;
+    let loaded = match format {
+        DictionaryFormat::IPADic => loader.load(src_dir)?,
+        DictionaryFormat::Utf8 => Utf8DicLoader {}.load(src_dir)?,
+        DictionaryFormat::MeCab => MeCabBinaryLoader {}.load(src_dir)?,
+    };
+    let user = match user_dict {
+        Some(path) => Some(loader.load_user_dictionary(path)?),
+        None => None,
+    };
 
     eprintln!(
         "{} {} Analyzing vocabulary...",
         style("[2/4]").bold().dim(),
         PAPER
     );
-    let mut cpt = CommonPrefixTree::default();
-    for (id, surface) in loaded.surfaces.iter() {
-        cpt.append(*id, surface);
-    }
+    let mut keys = loaded
+        .surfaces
+        .iter()
+        .map(|(id, surface)| (surface.as_str(), *id as u32))
+        .collect::<Vec<_>>();
+    keys.sort_unstable();
 
     eprintln!(
         "{} {} Recompiling dictionary...",
         style("[3/4]").bold().dim(),
         CLIP
     );
-    let da = DoubleArray::from_cpt(&cpt);
-
-    // DoubleArray only has one ID per surface form.
-    let used_wids = da.wids().collect();
-    loaded.ipadic.shrink_to_wids(&used_wids);
+    let da = SystemDoubleArray::from_sorted_keys(keys.into_iter());
 
     eprintln!(
         "{} {} Exporting dictionary...",
@@ -56,27 +95,44 @@ pub fn build(src_dir: &str, dist_dir: &str) -> Result<(), Box<dyn Error>> {
     let util = PathUtil::from(dist_dir.to_string());
     util.mkdirp().expect("Failed to create directory");
 
-    let mut serializer = AllocSerializer::<256>::default();
-    serializer.serialize_value(&da).unwrap();
-    let bytes = serializer.into_serializer().into_inner();
-    fs::write(util.da_path(), &bytes).expect("Failed to write dictionary");
+    let da_bytes = archive(&da);
     eprintln!("DoubleArray stats:");
     eprintln!("  elements: {}", da.base.len());
-    eprintln!("  bytes: {}", ByteSize(bytes.len() as u64));
+    eprintln!("  bytes: {}", ByteSize(da_bytes.len() as u64));
 
-    let mut serializer = AllocSerializer::<256>::default();
-    serializer
-        .serialize_value::<IPADic>(&loaded.ipadic)
-        .unwrap();
-    let bytes = serializer.into_serializer().into_inner();
-    fs::write(util.dict_path(), &bytes).expect("Failed to write dictionary");
+    let dict_bytes = archive(&loaded.ipadic);
     eprintln!("Dictionary stats:");
-    eprintln!("  bytes: {}", ByteSize(bytes.len() as u64));
+    eprintln!("  bytes: {}", ByteSize(dict_bytes.len() as u64));
 
-    let bytes = rmp_serde::to_vec(&loaded.word_set).unwrap();
-    fs::write(util.features_path(), &bytes).expect("Failed to write word features");
+    let features_bytes = rmp_serde::to_vec(&loaded.word_set).unwrap();
     eprintln!("Word features stats:");
-    eprintln!("  bytes: {}", ByteSize(bytes.len() as u64));
+    eprintln!("  bytes: {}", ByteSize(features_bytes.len() as u64));
+
+    let mut sections: Vec<(&str, &[u8])> = vec![
+        (SECTION_DOUBLE_ARRAY, &da_bytes),
+        (SECTION_DICTIONARY, &dict_bytes),
+        (SECTION_WORD_FEATURES, &features_bytes),
+    ];
+    let user_bytes = user.as_ref().map(archive);
+    if let (Some(user), Some(bytes)) = (&user, &user_bytes) {
+        sections.push((SECTION_USER_DICTIONARY, bytes));
+        eprintln!("User dictionary stats:");
+        eprintln!("  words: {}", user.len());
+        eprintln!("  bytes: {}", ByteSize(bytes.len() as u64));
+    }
+    let metadata = Metadata {
+        goya_version: env!("CARGO_PKG_VERSION").to_string(),
+        source: source_name(src_dir)?,
+        charset: loaded.charset.to_string(),
+        built_at: build_time()?,
+    };
+    fs::write(
+        util.dictionary_path(),
+        container::to_bytes(&metadata, &sections),
+    )
+    .expect("Failed to write dictionary");
+    // Loading it doesn't verify the checksums of the sections, so the written file is verified here
+    Container::from_bytes(&fs::read(util.dictionary_path())?)?.verify()?;
 
     let end = timer.elapsed();
     eprintln!(
@@ -87,3 +143,27 @@ pub fn build(src_dir: &str, dist_dir: &str) -> Result<(), Box<dyn Error>> {
     );
     Ok(())
 }
+
+/// `SOURCE_DATE_EPOCH` overrides the current time to reproduce the same dictionary
+/// https://reproducible-builds.org/specs/source-date-epoch/
+fn build_time() -> Result<u64, Box<dyn Error>> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => Ok(epoch.parse()?),
+        Err(_) => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+/// Name of the directory, e.g. mecab-ipadic-2.7.0-20070801
+fn source_name(src_dir: &str) -> Result<String, Box<dyn Error>> {
+    let path = fs::canonicalize(src_dir)?;
+    let name = path
+        .file_name()
+        .ok_or("Failed to get the name of the dictionary")?;
+    Ok(name.to_string_lossy().to_string())
+}
+
+pub fn archive<T: Serialize<AllocSerializer<256>>>(value: &T) -> AlignedVec {
+    let mut serializer = AllocSerializer::<256>::default();
+    serializer.serialize_value(value).unwrap();
+    serializer.into_serializer().into_inner()
+}
@@ -0,0 +1,63 @@
+use super::build::archive;
+use goya::container::SECTION_DOUBLE_ARRAY;
+use goya::Tokenizer;
+use goya_ipadic::ipadic::IPADic;
+use goya_ipadic::mecab_binary_exporter::MeCabBinaryExporter;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub enum ExportFormat {
+    /// sys.dic, unk.dic, matrix.bin, char.bin and dicrc for `mecab -d`
+    MeCab,
+    /// da.bin (da-utf8.bin with the utf8-double-array feature), dict.bin and features.bin,
+    /// which goya-core and goya-features for WebAssembly embed
+    Archives,
+}
+impl FromStr for ExportFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mecab" => Ok(ExportFormat::MeCab),
+            "archives" => Ok(ExportFormat::Archives),
+            _ => Err("no match"),
+        }
+    }
+}
+
+pub fn export(
+    tokenizer: &Tokenizer<IPADic>,
+    format: ExportFormat,
+    dist_dir: &str,
+) -> Result<(), Box<dyn Error>> {
+    let features = tokenizer
+        .word_features()
+        .ok_or("Word features are not loaded")?;
+    match format {
+        ExportFormat::MeCab => MeCabBinaryExporter {}.export(
+            tokenizer.double_array(),
+            tokenizer.system_dictionary(),
+            features,
+            tokenizer.user_dictionary(),
+            dist_dir,
+        )?,
+        ExportFormat::Archives => {
+            if tokenizer.user_dictionary().is_some() {
+                eprintln!("The user dictionary is not exported. Load it with `load_user_dictionary` of goya-core");
+            }
+            let dir = Path::new(dist_dir);
+            fs::create_dir_all(dir)?;
+            // Named after the kind of the double array like the section of dict.goya
+            fs::write(
+                dir.join(format!("{}.bin", SECTION_DOUBLE_ARRAY)),
+                archive(tokenizer.double_array()),
+            )?;
+            fs::write(dir.join("dict.bin"), archive(tokenizer.system_dictionary()))?;
+            fs::write(dir.join("features.bin"), rmp_serde::to_vec(features)?)?;
+        }
+    }
+    eprintln!("Exported to {}", dist_dir);
+    Ok(())
+}
@@ -1,16 +1,28 @@
 mod build;
+mod export;
 mod path_util;
 mod repl;
 
+use bytesize::ByteSize;
 use clap::Parser;
-use futures::executor::block_on;
-use futures::future;
-use goya::double_array::DoubleArray;
+use goya::container::{
+    Container, FORMAT_VERSION, SECTION_DICTIONARY, SECTION_DOUBLE_ARRAY, SECTION_USER_DICTIONARY,
+    SECTION_WORD_FEATURES,
+};
+use goya::user_dictionary::UserDictionary;
+use goya::word_features::WordFeaturesMap;
+use goya::Tokenizer;
+use goya_ipadic::dump;
 use goya_ipadic::ipadic::IPADic;
+use goya_ipadic::ipadic_loader::IPADicLoader;
+use memmap2::Mmap;
 use path_util::PathUtil;
 use repl::Format;
-use rkyv::{archived_root, Deserialize, Infallible};
-use std::fs;
+use rkyv::{from_bytes, AlignedVec};
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process;
 
 #[derive(Parser)]
 struct Opts {
@@ -19,6 +31,21 @@ struct Opts {
     dicdir: Option<String>,
     #[clap(short, long, default_value = "plain")]
     format: Format,
+    /// Output the N best results like MeCab's `-N` option
+    #[clap(short = 'N', long, default_value = "1")]
+    nbest: usize,
+    /// Output the marginal probability of each morpheme like MeCab's `-m` option
+    #[clap(short, long)]
+    marginal: bool,
+    /// Temperature of the marginal probabilities. A higher value gives a flatter distribution
+    #[clap(long, default_value = "1000", parse(try_from_str = parse_temperature))]
+    temperature: f64,
+    /// Output start/end offsets of each morpheme in chars, UTF-8 bytes and UTF-16 code units
+    #[clap(long)]
+    offsets: bool,
+    /// Path to a user dictionary CSV. It's used instead of the one compiled into the dicdir
+    #[clap(short, long)]
+    userdic: Option<String>,
     #[clap(subcommand)]
     subcmd: Option<SubCommand>,
 }
@@ -26,14 +53,49 @@ struct Opts {
 #[derive(Parser)]
 enum SubCommand {
     Compile(Compile),
+    Export(Export),
+    Dump(Dump),
+    /// Prints the metadata and the sections of the compiled dictionary and verifies their checksums
+    Info,
     Clean,
 }
 
 /// A subcommand for controlling testing
 #[derive(Parser)]
 struct Compile {
-    /// Path to the IPAdic directory
+    /// Path to the dictionary directory
     dicpath: String,
+    /// Format of the dictionary: ipadic, utf8 (IPADIC in UTF-8, e.g. UniDic and mecab-ko-dic; unidic and ko-dic are
+    /// the aliases of it) or mecab (binary dictionaries compiled by MeCab)
+    #[clap(long, default_value = "ipadic")]
+    format: build::DictionaryFormat,
+    /// Path to a user dictionary CSV to compile along with the IPAdic
+    #[clap(short, long)]
+    userdic: Option<String>,
+}
+
+/// Exports the compiled dictionary for other analyzers
+#[derive(Parser)]
+struct Export {
+    /// Path to the output directory
+    dest: String,
+    /// Format of the output: mecab (binary dictionaries for `mecab -d`) or archives (da.bin, dict.bin and features.bin to embed)
+    #[clap(long, default_value = "mecab")]
+    format: export::ExportFormat,
+}
+
+/// Prints the compiled dictionary as the CSV of the words
+#[derive(Parser)]
+struct Dump {
+    /// Print the connection costs like matrix.def instead
+    #[clap(long)]
+    matrix: bool,
+    /// Print the unknown words like unk.def instead
+    #[clap(long)]
+    unknown: bool,
+    /// Print the words of the user dictionary compiled along with the dictionary instead
+    #[clap(long)]
+    user: bool,
 }
 
 fn main() {
@@ -43,47 +105,168 @@ fn main() {
         .dicdir
         .unwrap_or_else(|| base_dir.join("dict").to_str().unwrap().to_string());
     match opts.subcmd {
-        Some(SubCommand::Compile(c)) => match build::build(&c.dicpath, &dicdir) {
-            Ok(_) => {}
-            Err(err) => {
+        Some(SubCommand::Compile(c)) => {
+            match build::build(&c.dicpath, &dicdir, c.format, c.userdic.as_deref()) {
+                Ok(_) => {}
+                Err(err) => {
+                    println!("{:?}", err);
+                }
+            }
+        }
+        Some(SubCommand::Export(e)) => {
+            let tokenizer = load_tokenizer(&PathUtil::from(dicdir));
+            if let Err(err) = export::export(&tokenizer, e.format, &e.dest) {
                 println!("{:?}", err);
             }
-        },
+        }
+        Some(SubCommand::Dump(d)) => {
+            let tokenizer = load_tokenizer(&PathUtil::from(dicdir));
+            let dict = tokenizer.system_dictionary();
+            let features = tokenizer.word_features().unwrap();
+            let out = if d.matrix {
+                dump::to_matrix_def(dict)
+            } else if d.unknown {
+                dump::to_csv(&dump::unknown_words(dict, features).unwrap())
+            } else if d.user {
+                match tokenizer.user_dictionary() {
+                    Some(user) => dump::to_csv(&dump::user_words(user).unwrap()),
+                    None => String::new(),
+                }
+            } else {
+                dump::to_csv(&dump::known_words(tokenizer.double_array(), dict, features).unwrap())
+            };
+            print!("{}", out);
+        }
+        Some(SubCommand::Info) => {
+            let bytes = read_aligned(PathUtil::from(dicdir).dictionary_path())
+                .expect("Failed to load dictionary");
+            let container = unwrap_or_exit(Container::from_bytes(&bytes));
+            let metadata = container.metadata();
+            println!("source: {}", metadata.source);
+            println!("charset: {}", metadata.charset);
+            println!(
+                "compiled by: Goya {} (format version {})",
+                metadata.goya_version, FORMAT_VERSION
+            );
+            println!("built at: {} (Unix time)", metadata.built_at);
+            println!("sections:");
+            for section in container.sections() {
+                println!(
+                    "  {}: {} (CRC-32 {:08x})",
+                    section.name,
+                    ByteSize(section.len as u64),
+                    section.checksum
+                );
+            }
+            unwrap_or_exit(container.verify());
+            println!("checksums: ok");
+        }
         Some(SubCommand::Clean) => {
             let util = PathUtil::from(dicdir);
-            fs::remove_file(util.da_path()).expect("Failed to delete file");
-            fs::remove_file(util.dict_path()).expect("Failed to delete file");
+            fs::remove_file(util.dictionary_path()).expect("Failed to delete file");
         }
         _ => {
             let util = PathUtil::from(dicdir);
-
-            let da_fut = async {
-                let encoded = fs::read(util.da_path()).expect("Failed to load dictionary");
-                let archived = unsafe { archived_root::<DoubleArray>(&encoded[..]) };
-                archived.deserialize(&mut Infallible).unwrap()
-            };
-            let ipadic_fut = async {
-                let encoded = fs::read(util.dict_path()).expect("Failed to load vocabulary");
-                let archived = unsafe { archived_root::<IPADic>(&encoded[..]) };
-                archived.deserialize(&mut Infallible).unwrap()
+            // The archives are used without deserialization to start up instantly.
+            // The checksums of the sections are not verified since it reads the whole file, while the validation
+            // of the archives skips the bulk of them, i.e. the arrays of numbers
+            let file = mmap(util.dictionary_path()).expect("Failed to load dictionary");
+            let container = unwrap_or_exit(Container::from_bytes(&file));
+            let mut tokenizer = unwrap_or_exit(Tokenizer::from_archived_ref::<IPADic>(
+                unwrap_or_exit(container.required_section(SECTION_DOUBLE_ARRAY)),
+                unwrap_or_exit(container.required_section(SECTION_DICTIONARY)),
+            ))
+            .with_features(load_features(&container));
+            let user = match opts.userdic {
+                Some(path) => Some(unwrap_user_dictionary_or_exit(
+                    &path,
+                    IPADicLoader {}.load_user_dictionary(&path),
+                )),
+                None => load_user_dictionary(&container),
             };
-            let features_fut = async {
-                let encoded = fs::read(util.features_path()).expect("Failed to load surfaces");
-                rmp_serde::from_slice(&encoded[..]).unwrap()
-            };
-
-            let (ipadic, word_set) = block_on(future::join(ipadic_fut, features_fut));
-            let da = block_on(da_fut);
+            tokenizer.set_user_dictionary(user);
             repl::start(repl::ReplContext {
-                da: &da,
-                dict: &ipadic,
-                word_set: &word_set,
+                tokenizer: &tokenizer,
                 format: opts.format,
+                nbest: opts.nbest,
+                temperature: if opts.marginal {
+                    Some(opts.temperature)
+                } else {
+                    None
+                },
+                offsets: opts.offsets,
             })
             .unwrap();
-            std::thread::spawn(move || drop(ipadic));
-            std::thread::spawn(move || drop(da));
-            std::thread::spawn(move || drop(word_set));
         }
     }
 }
+
+/// Loads the dictionary with the user dictionary compiled along with it
+fn load_tokenizer(util: &PathUtil) -> Tokenizer<IPADic> {
+    let bytes = read_aligned(util.dictionary_path()).expect("Failed to load dictionary");
+    let container = unwrap_or_exit(Container::from_bytes(&bytes));
+    let mut tokenizer = unwrap_or_exit(Tokenizer::<IPADic>::from_archived(
+        unwrap_or_exit(container.required_section(SECTION_DOUBLE_ARRAY)),
+        unwrap_or_exit(container.required_section(SECTION_DICTIONARY)),
+    ))
+    .with_features(load_features(&container));
+    tokenizer.set_user_dictionary(load_user_dictionary(&container));
+    tokenizer
+}
+
+fn load_user_dictionary(container: &Container) -> Option<UserDictionary> {
+    container
+        .section(SECTION_USER_DICTIONARY)
+        .map(|bytes| unwrap_or_exit(from_bytes::<UserDictionary>(bytes)))
+}
+
+/// Archives must be aligned to be validated
+fn read_aligned<P: AsRef<Path>>(path: P) -> std::io::Result<AlignedVec> {
+    let mut bytes = AlignedVec::new();
+    bytes.extend_from_slice(&fs::read(path)?);
+    Ok(bytes)
+}
+
+/// Parses `--temperature`, which must be a finite positive number
+fn parse_temperature(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t.is_finite() && t > 0.0 => Ok(t),
+        _ => Err(String::from("must be a finite positive number")),
+    }
+}
+
+/// Exits when the dictionary is broken or compiled by another version of Goya
+fn unwrap_or_exit<T, E: Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        exit_with(&format!(
+            "Failed to load the dictionary: {}\nCompile the dictionary again with `goya compile`",
+            err
+        ))
+    })
+}
+
+/// Exits when the user dictionary CSV is broken
+fn unwrap_user_dictionary_or_exit<T, E: Display>(path: &str, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        exit_with(&format!(
+            "Failed to load the user dictionary {}: {}",
+            path, err
+        ))
+    })
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn load_features(container: &Container) -> WordFeaturesMap {
+    let features = unwrap_or_exit(container.required_section(SECTION_WORD_FEATURES));
+    rmp_serde::from_slice(features).unwrap()
+}
+
+fn mmap<P: AsRef<Path>>(path: P) -> std::io::Result<Mmap> {
+    let file = File::open(path)?;
+    // The file must not be modified while it's mapped, e.g. by `goya compile` in another process
+    unsafe { Mmap::map(&file) }
+}
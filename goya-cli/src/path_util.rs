@@ -14,15 +14,7 @@ impl PathUtil {
         create_dir_all(&self.base)
     }
 
-    pub fn da_path(&self) -> PathBuf {
-        Path::new(&self.base).join("da.bin")
-    }
-
-    pub fn dict_path(&self) -> PathBuf {
-        Path::new(&self.base).join("dict.bin")
-    }
-
-    pub fn features_path(&self) -> PathBuf {
-        Path::new(&self.base).join("features.bin")
+    pub fn dictionary_path(&self) -> PathBuf {
+        Path::new(&self.base).join("dict.goya")
     }
 }
@@ -1,9 +1,10 @@
+use goya::dictionary::Dictionary;
 use goya::dot;
-use goya::double_array::DoubleArray;
-use goya::id::WordIdentifier;
+use goya::double_array::Trie;
 use goya::lattice::Lattice;
-use goya::word_features::WordFeaturesMap;
-use goya_ipadic::ipadic::IPADic;
+use goya::marginal::Marginals;
+use goya::Tokenizer;
+use std::error::Error;
 use std::io::{stdin, stdout, BufRead, BufWriter, Write};
 use std::str::FromStr;
 
@@ -23,56 +24,84 @@ impl FromStr for Format {
     }
 }
 
-pub struct ReplContext<'a> {
-    pub da: &'a DoubleArray,
-    pub dict: &'a IPADic,
-    pub word_set: &'a WordFeaturesMap,
+pub struct ReplContext<'a, D, T> {
+    pub tokenizer: &'a Tokenizer<D, T>,
     pub format: Format,
+    pub nbest: usize,
+    /// Temperature of the marginal probabilities. They aren't computed when it's `None`
+    pub temperature: Option<f64>,
+    pub offsets: bool,
 }
 
-pub fn start(opt: ReplContext) -> Result<(), std::io::Error> {
+pub fn start<D: Dictionary, T: Trie>(opt: ReplContext<D, T>) -> Result<(), Box<dyn Error>> {
     let out = stdout();
     let mut out = BufWriter::new(out.lock());
+    let dict = &opt.tokenizer.dictionary();
 
     for line in stdin().lock().lines() {
         match line {
             Ok(line) if line.is_empty() => continue,
             Ok(line) => {
-                let lattice = Lattice::parse(&line, opt.da, opt.dict);
+                let lattice = opt.tokenizer.parse(&line)?;
                 match opt.format {
                     Format::Dot => {
-                        writeln!(out, "{}", dot::render(&lattice, opt.dict).unwrap())?;
+                        writeln!(out, "{}", dot::render(&lattice, dict)?)?;
                     }
                     Format::Plain => {
-                        if let Some(path) = lattice.find_best() {
-                            for wid in path.into_iter() {
-                                let (surface_form, features) = match wid {
-                                    WordIdentifier::Unknown(id, surface_form) => {
-                                        (surface_form, opt.word_set.get_unknown(&id).unwrap())
-                                    }
-                                    WordIdentifier::Known(id, surface_form) => {
-                                        (surface_form, opt.word_set.get_known(&id).unwrap())
-                                    }
-                                };
-                                writeln!(
-                                    out,
-                                    "{}\t{}",
-                                    surface_form,
-                                    features
-                                        .into_iter()
-                                        .map(|f| f.to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(",")
-                                )?;
-                            }
-                            writeln!(out, "EOS")?;
-                            out.flush()?;
+                        let marginals = match opt.temperature {
+                            Some(t) => Some(lattice.marginals(dict, t)?),
+                            None => None,
+                        };
+                        let paths: Vec<_> = if opt.nbest > 1 {
+                            lattice
+                                .nbest_paths(dict, opt.nbest)?
+                                .into_iter()
+                                .map(|(path, _)| path)
+                                .collect()
+                        } else {
+                            vec![lattice.find_best_path().ok_or(goya::Error::PathNotFound)?]
+                        };
+                        for path in paths.iter() {
+                            write_path(&mut out, &lattice, path, &opt, marginals.as_ref())?;
                         }
+                        out.flush()?;
                     }
                 }
             }
-            Err(err) => return Err(err),
+            Err(err) => return Err(err.into()),
         }
     }
     Ok(())
 }
+
+fn write_path<W: Write, D: Dictionary, T: Trie>(
+    out: &mut W,
+    lattice: &Lattice,
+    path: &[(usize, usize)],
+    opt: &ReplContext<D, T>,
+    marginals: Option<&Marginals>,
+) -> Result<(), Box<dyn Error>> {
+    let tokens = opt.tokenizer.tokens(lattice, path)?;
+    for ((i, j), token) in path.iter().zip(tokens.iter()) {
+        write!(out, "{}\t{}", token.surface, token.features.join(","))?;
+        if let Some(marginals) = marginals {
+            write!(out, "\t{:.6}", marginals.node_probability(*i, *j))?;
+        }
+        if opt.offsets {
+            let offsets = &token.offsets;
+            write!(
+                out,
+                "\t{}\t{}\t{}\t{}\t{}\t{}",
+                offsets.chars.start,
+                offsets.chars.end,
+                offsets.bytes.start,
+                offsets.bytes.end,
+                offsets.utf16.start,
+                offsets.utf16.end
+            )?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "EOS")?;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use goya::common_prefix_tree::CommonPrefixTree;
+use goya::double_array::{DoubleArray, Trie};
+use goya::utf8_double_array::Utf8DoubleArray;
+
+/// Pseudo-random words of 1 to 8 chars uniformly drawn from hiragana, katakana and 3,000 kanji.
+/// They aren't distributed like the vocabulary of IPADIC, e.g. its words share more prefixes
+fn words(n: usize) -> Vec<String> {
+    let mut seed: u64 = 42;
+    let mut next = move || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as u32
+    };
+    let mut words = (0..n)
+        .map(|_| {
+            let len = 1 + next() % 8;
+            (0..len)
+                .map(|_| {
+                    let c = match next() % 10 {
+                        0..=3 => 0x3041 + next() % 86, // hiragana
+                        4..=6 => 0x30A1 + next() % 90, // katakana
+                        _ => 0x4E00 + next() % 3000,   // common kanji
+                    };
+                    char::from_u32(c).unwrap()
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+    words.sort();
+    words.dedup();
+    words
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    for n in [10_000, 100_000] {
+        let words = words(n);
+        group.bench_with_input(BenchmarkId::new("from_cpt", n), &words, |b, words| {
+            b.iter(|| {
+                let mut cpt = CommonPrefixTree::default();
+                for (id, word) in words.iter().enumerate() {
+                    cpt.append(id + 1, word);
+                }
+                DoubleArray::from_cpt(&cpt)
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("utf8_from_sorted_keys", n),
+            &words,
+            |b, words| {
+                b.iter(|| {
+                    Utf8DoubleArray::from_sorted_keys(
+                        words.iter().zip(1..).map(|(word, id)| (word.as_str(), id)),
+                    )
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("from_sorted_keys", n),
+            &words,
+            |b, words| {
+                b.iter(|| {
+                    DoubleArray::from_sorted_keys(
+                        words.iter().zip(1..).map(|(word, id)| (word.as_str(), id)),
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Common prefix search from every char of a text like `Lattice::parse`
+fn search_all<T: Trie>(da: &T, text: &str) -> usize {
+    text.char_indices()
+        .map(|(i, _)| da.common_prefix_search(&text[i..]).count())
+        .sum()
+}
+
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("common_prefix_search");
+    let words = words(100_000);
+    let keys = || words.iter().zip(1..).map(|(word, id)| (word.as_str(), id));
+    let da = DoubleArray::from_sorted_keys(keys());
+    let utf8 = Utf8DoubleArray::from_sorted_keys(keys());
+    // Half of the words are in the text
+    let text = words.iter().step_by(2).cloned().collect::<String>();
+    eprintln!(
+        "archived bytes: DoubleArray {}, Utf8DoubleArray {}",
+        rkyv::to_bytes::<_, 256>(&da).unwrap().len(),
+        rkyv::to_bytes::<_, 256>(&utf8).unwrap().len()
+    );
+    group.bench_function("DoubleArray", |b| b.iter(|| search_all(&da, &text)));
+    group.bench_function("Utf8DoubleArray", |b| b.iter(|| search_all(&utf8, &text)));
+    group.finish();
+}
+
+criterion_group!(benches, build, search);
+criterion_main!(benches);
@@ -1,11 +1,25 @@
+use super::error::{Error, Result};
+use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
 
 const CLASS_DEFAULT: &str = "DEFAULT";
+pub const CLASS_SPACE: &str = "SPACE";
+/// The max number of chars following the first one in a grouped unknown word, same as MeCab
+const MAX_GROUPING_SIZE: usize = 24;
 
 #[derive(
-    Debug, PartialEq, Eq, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
 )]
+#[archive(check_bytes)]
 pub enum InvokeTiming {
     Fallback,
     Always,
@@ -13,76 +27,287 @@ pub enum InvokeTiming {
 #[derive(
     Debug, PartialEq, Eq, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
 )]
+#[archive(check_bytes)]
 pub struct CharDefinition {
     pub class: String,
     pub timing: InvokeTiming,
     pub group_by_same_kind: bool,
     pub len: usize,
-    pub compatibilities: HashSet<String>, // elements = class name
 }
-impl CharDefinition {
-    pub fn compatible_with(&self, class_name: &str) -> bool {
-        self.class.eq(class_name) || self.compatibilities.contains(class_name)
+
+/// A borrowed `CharDefinition`, which both of the owned and the archived dictionaries can return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharDefinitionRef<'a> {
+    pub class: &'a str,
+    pub timing: InvokeTiming,
+    pub group_by_same_kind: bool,
+    pub len: usize,
+}
+impl<'a> From<&'a CharDefinition> for CharDefinitionRef<'a> {
+    fn from(def: &'a CharDefinition) -> Self {
+        CharDefinitionRef {
+            class: &def.class,
+            timing: def.timing,
+            group_by_same_kind: def.group_by_same_kind,
+            len: def.len,
+        }
+    }
+}
+impl<'a> From<&'a ArchivedCharDefinition> for CharDefinitionRef<'a> {
+    fn from(def: &'a ArchivedCharDefinition) -> Self {
+        CharDefinitionRef {
+            class: &def.class,
+            timing: match def.timing {
+                ArchivedInvokeTiming::Fallback => InvokeTiming::Fallback,
+                ArchivedInvokeTiming::Always => InvokeTiming::Always,
+            },
+            group_by_same_kind: def.group_by_same_kind,
+            len: def.len as usize,
+        }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
 pub struct CharClass {
     range: (u32, u32),
     class: String,
+    compatibilities: IndexSet<String>, // elements = class name
 }
 impl CharClass {
-    pub fn from(range: (u32, u32), class: String) -> CharClass {
-        CharClass { range, class }
+    pub fn from(range: (u32, u32), class: String, compatibilities: IndexSet<String>) -> CharClass {
+        CharClass {
+            range,
+            class,
+            compatibilities,
+        }
+    }
+
+    /// (first code point, last code point)
+    pub fn range(&self) -> (u32, u32) {
+        self.range
+    }
+
+    pub fn class(&self) -> &str {
+        &self.class
+    }
+
+    pub fn compatibilities(&self) -> &IndexSet<String> {
+        &self.compatibilities
     }
 
     pub fn in_range(&self, c: &char) -> bool {
         let code = *c as u32;
         self.range.0 <= code && code <= self.range.1
     }
+
+    pub fn compatible_with(&self, class_name: &str) -> bool {
+        self.class.eq(class_name) || self.compatibilities.contains(class_name)
+    }
+}
+
+impl ArchivedCharClass {
+    pub fn in_range(&self, c: &char) -> bool {
+        let code = *c as u32;
+        self.range.0 <= code && code <= self.range.1
+    }
+
+    pub fn compatible_with(&self, class_name: &str) -> bool {
+        self.class == class_name || self.compatibilities.contains(class_name)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
 pub struct CharClassifier {
-    chars: HashMap<String, CharDefinition>,
+    chars: IndexMap<String, CharDefinition>,
     ranges: Vec<CharClass>,
 }
 impl CharClassifier {
-    pub fn from(chars: HashMap<String, CharDefinition>, ranges: Vec<CharClass>) -> CharClassifier {
+    pub fn from(chars: IndexMap<String, CharDefinition>, ranges: Vec<CharClass>) -> CharClassifier {
         CharClassifier { chars, ranges }
     }
 
-    pub fn classify(&self, c: &char) -> &CharDefinition {
+    /// key = class name
+    pub fn definitions(&self) -> &IndexMap<String, CharDefinition> {
+        &self.chars
+    }
+
+    /// Later ranges override earlier ones
+    pub fn ranges(&self) -> &[CharClass] {
+        &self.ranges
+    }
+
+    pub fn classify(&self, c: &char) -> Result<&CharDefinition> {
         let class = self.get_class_name(c);
-        self.chars.get(class).unwrap()
+        self.chars
+            .get(class)
+            .ok_or_else(|| Error::CharClassNotFound(class.to_string()))
     }
 
-    pub fn take_unknown_chars(&self, def: &CharDefinition, text: &str, start: &usize) -> String {
-        if !def.group_by_same_kind {
-            return text.chars().skip(*start).take(def.len).collect();
-        }
+    /// Returns the lengths of unknown words which start at `start` and end until `end` like MeCab.
+    /// When the class groups chars, the longest run of the same kind comes first.
+    /// Then words of 1 to `len` chars follow, excluding the grouped one.
+    pub fn unknown_word_lengths(
+        &self,
+        text: &str,
+        start: &usize,
+        end: &usize,
+    ) -> Result<Vec<usize>> {
+        Classifier::unknown_word_lengths(self, text, start, end)
+    }
 
-        let mut len = 0;
-        text.chars()
-            .enumerate()
-            .skip(*start)
-            .take_while(|(_, c)| {
-                if def.len != 0 && len >= def.len || !def.compatible_with(self.get_class_name(c)) {
-                    return false;
-                }
-                len += 1;
-                true
-            })
-            .map(|(_, c)| c)
-            .collect()
+    /// Later definitions override earlier ones like MeCab
+    fn get_class(&self, c: &char) -> Option<&CharClass> {
+        self.ranges.iter().rev().find(|class| class.in_range(c))
     }
 
     fn get_class_name(&self, c: &char) -> &str {
-        self.ranges
-            .iter()
-            .find(|class| class.in_range(c))
+        self.get_class(c)
             .map(|class| class.class.as_str())
-            .unwrap_or_else(|| CLASS_DEFAULT)
+            .unwrap_or(CLASS_DEFAULT)
+    }
+}
+
+impl ArchivedCharClassifier {
+    pub fn classify(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+        let class = self.get_class_name(c);
+        self.chars
+            .get(class)
+            .map(CharDefinitionRef::from)
+            .ok_or_else(|| Error::CharClassNotFound(class.to_string()))
+    }
+
+    /// Same as `CharClassifier::unknown_word_lengths`
+    pub fn unknown_word_lengths(
+        &self,
+        text: &str,
+        start: &usize,
+        end: &usize,
+    ) -> Result<Vec<usize>> {
+        Classifier::unknown_word_lengths(self, text, start, end)
+    }
+
+    fn get_class(&self, c: &char) -> Option<&ArchivedCharClass> {
+        self.ranges.iter().rev().find(|class| class.in_range(c))
+    }
+
+    fn get_class_name(&self, c: &char) -> &str {
+        self.get_class(c)
+            .map(|class| class.class.as_str())
+            .unwrap_or(CLASS_DEFAULT)
+    }
+}
+
+/// Shares the comparison of classes between `CharClass` and `ArchivedCharClass`
+trait Class {
+    fn name(&self) -> &str;
+    fn compatible_with(&self, class_name: &str) -> bool;
+    /// Returns true if any of the compatibilities satisfies `f`
+    fn any_compatibility<F: FnMut(&str) -> bool>(&self, f: F) -> bool;
+}
+impl Class for CharClass {
+    fn name(&self) -> &str {
+        &self.class
+    }
+
+    fn compatible_with(&self, class_name: &str) -> bool {
+        CharClass::compatible_with(self, class_name)
+    }
+
+    fn any_compatibility<F: FnMut(&str) -> bool>(&self, mut f: F) -> bool {
+        self.compatibilities.iter().any(|name| f(name))
+    }
+}
+impl Class for ArchivedCharClass {
+    fn name(&self) -> &str {
+        &self.class
+    }
+
+    fn compatible_with(&self, class_name: &str) -> bool {
+        ArchivedCharClass::compatible_with(self, class_name)
+    }
+
+    fn any_compatibility<F: FnMut(&str) -> bool>(&self, mut f: F) -> bool {
+        self.compatibilities.iter().any(|name| f(name))
+    }
+}
+
+/// Shares the grouping of unknown words between the owned and the archived classifiers
+trait Classifier {
+    type Class: Class;
+
+    fn definition(&self, c: &char) -> Result<CharDefinitionRef<'_>>;
+    /// Later definitions override earlier ones like MeCab
+    fn class_of(&self, c: &char) -> Option<&Self::Class>;
+
+    /// Returns true if both chars belong to a common class
+    fn is_kind_of(&self, a: &char, b: &char) -> bool {
+        match (self.class_of(a), self.class_of(b)) {
+            (Some(a), Some(b)) => {
+                a.compatible_with(b.name()) || b.any_compatibility(|name| a.compatible_with(name))
+            }
+            (Some(class), None) | (None, Some(class)) => class.compatible_with(CLASS_DEFAULT),
+            (None, None) => true,
+        }
+    }
+
+    fn unknown_word_lengths(&self, text: &str, start: &usize, end: &usize) -> Result<Vec<usize>> {
+        let chars = text
+            .chars()
+            .skip(*start)
+            .take(end.saturating_sub(*start))
+            .collect::<Vec<_>>();
+        let first = match chars.first() {
+            Some(c) => c,
+            None => return Ok(vec![]),
+        };
+        let def = self.definition(first)?;
+        let mut lengths = vec![];
+
+        let mut group = None;
+        if def.group_by_same_kind {
+            // Each char is compared with the previous one
+            let grouped = chars
+                .windows(2)
+                .take_while(|pair| self.is_kind_of(&pair[0], &pair[1]))
+                .count();
+            if grouped <= MAX_GROUPING_SIZE {
+                lengths.push(grouped + 1);
+            }
+            group = Some(grouped + 1);
+        }
+
+        // Each char is compared with the first one
+        let same_kind = chars
+            .iter()
+            .take(def.len)
+            .take_while(|c| self.is_kind_of(first, c))
+            .count();
+        lengths.extend((1..=same_kind).filter(|len| Some(*len) != group));
+        Ok(lengths)
+    }
+}
+impl Classifier for CharClassifier {
+    type Class = CharClass;
+
+    fn definition(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+        self.classify(c).map(CharDefinitionRef::from)
+    }
+
+    fn class_of(&self, c: &char) -> Option<&CharClass> {
+        self.get_class(c)
+    }
+}
+impl Classifier for ArchivedCharClassifier {
+    type Class = ArchivedCharClass;
+
+    fn definition(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+        self.classify(c)
+    }
+
+    fn class_of(&self, c: &char) -> Option<&ArchivedCharClass> {
+        self.get_class(c)
     }
 }
 
@@ -92,39 +317,117 @@ mod tests {
 
     #[test]
     fn compatible_with_without_compatibilities() {
-        let def_a = CharDefinition {
-            class: String::from("A"),
-            timing: InvokeTiming::Always,
-            group_by_same_kind: false,
-            len: 2,
-            compatibilities: HashSet::new(),
-        };
-        assert_eq!(def_a.compatible_with("A"), true);
-        assert_eq!(def_a.compatible_with("B"), false);
+        let class_a = CharClass::from((0, 0), String::from("A"), IndexSet::new());
+        assert!(class_a.compatible_with("A"));
+        assert!(!class_a.compatible_with("B"));
     }
 
     #[test]
     fn compatible_with_with_compatibilities() {
-        let mut compatibilities = HashSet::new();
+        let mut compatibilities = IndexSet::new();
         compatibilities.insert(String::from("B"));
-        let def_a = CharDefinition {
-            class: String::from("A"),
-            timing: InvokeTiming::Always,
-            group_by_same_kind: false,
-            len: 2,
-            compatibilities,
-        };
-        assert_eq!(def_a.compatible_with("A"), true);
-        assert_eq!(def_a.compatible_with("B"), true);
-        assert_eq!(def_a.compatible_with("C"), false);
+        let class_a = CharClass::from((0, 0), String::from("A"), compatibilities);
+        assert!(class_a.compatible_with("A"));
+        assert!(class_a.compatible_with("B"));
+        assert!(!class_a.compatible_with("C"));
     }
 
     #[test]
     fn in_range() {
-        let class = CharClass::from((1, 2), String::new());
-        assert_eq!(class.in_range(&(0 as char)), false);
-        assert_eq!(class.in_range(&(1 as char)), true);
-        assert_eq!(class.in_range(&(2 as char)), true);
-        assert_eq!(class.in_range(&(3 as char)), false);
+        let class = CharClass::from((1, 2), String::new(), IndexSet::new());
+        assert!(!class.in_range(&(0 as char)));
+        assert!(class.in_range(&(1 as char)));
+        assert!(class.in_range(&(2 as char)));
+        assert!(!class.in_range(&(3 as char)));
+    }
+
+    /// KATAKANA groups chars up to 2 chars, ALPHA groups chars without length,
+    /// and `ー` is both of KATAKANA and HIRAGANA
+    fn setup() -> CharClassifier {
+        let mut chars = IndexMap::new();
+        for (class, group_by_same_kind, len) in [
+            ("DEFAULT", false, 1),
+            ("KATAKANA", true, 2),
+            ("HIRAGANA", false, 3),
+            ("ALPHA", true, 0),
+        ] {
+            chars.insert(
+                String::from(class),
+                CharDefinition {
+                    class: String::from(class),
+                    timing: InvokeTiming::Always,
+                    group_by_same_kind,
+                    len,
+                },
+            );
+        }
+        let mut compatibilities = IndexSet::new();
+        compatibilities.insert(String::from("HIRAGANA"));
+        let ranges = vec![
+            CharClass::from((0x3041, 0x309F), String::from("HIRAGANA"), IndexSet::new()),
+            CharClass::from((0x30A1, 0x30FF), String::from("KATAKANA"), IndexSet::new()),
+            CharClass::from((0x30FC, 0x30FC), String::from("KATAKANA"), compatibilities),
+            CharClass::from((0x61, 0x7A), String::from("ALPHA"), IndexSet::new()),
+        ];
+        CharClassifier::from(chars, ranges)
+    }
+
+    #[test]
+    fn later_ranges_override_earlier_ones() {
+        let classifier = setup();
+        assert_eq!(classifier.classify(&'ー').unwrap().class, "KATAKANA");
+        assert_eq!(classifier.classify(&'ア').unwrap().class, "KATAKANA");
+        assert_eq!(classifier.classify(&'0').unwrap().class, "DEFAULT");
+    }
+
+    #[test]
+    fn unknown_word_lengths_with_group_and_len() {
+        let classifier = setup();
+        assert_eq!(
+            classifier.unknown_word_lengths("アイウあ", &0, &4).unwrap(),
+            vec![3, 1, 2]
+        );
+        assert_eq!(
+            classifier.unknown_word_lengths("アイウあ", &1, &4).unwrap(),
+            vec![2, 1]
+        );
+        assert_eq!(
+            classifier.unknown_word_lengths("アイウあ", &0, &2).unwrap(),
+            vec![2, 1]
+        );
+        assert_eq!(
+            classifier.unknown_word_lengths("アイウあ", &3, &4).unwrap(),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn unknown_word_lengths_without_len() {
+        let classifier = setup();
+        assert_eq!(
+            classifier.unknown_word_lengths("abcア", &0, &4).unwrap(),
+            vec![3]
+        );
+        let long = "a".repeat(MAX_GROUPING_SIZE + 2);
+        assert_eq!(
+            classifier
+                .unknown_word_lengths(&long, &0, &(MAX_GROUPING_SIZE + 2))
+                .unwrap(),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn unknown_word_lengths_with_compatibilities() {
+        let classifier = setup();
+        // ー continues both of katakana and hiragana
+        assert_eq!(
+            classifier.unknown_word_lengths("アーあ", &0, &3).unwrap(),
+            vec![3, 1, 2]
+        );
+        assert_eq!(
+            classifier.unknown_word_lengths("あーア", &0, &3).unwrap(),
+            vec![1, 2]
+        );
     }
 }
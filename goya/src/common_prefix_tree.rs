@@ -25,7 +25,7 @@ impl CommonPrefixTree {
     }
 
     pub fn entires_dfs(&self) -> Vec<(String, &CommonPrefixTree)> {
-        self.dfs_collect(&String::new())
+        self.dfs_collect("")
     }
 
     fn dfs_collect(&self, prefix: &str) -> Vec<(String, &CommonPrefixTree)> {
@@ -40,10 +40,7 @@ impl CommonPrefixTree {
 
     fn append_chars(&mut self, id: usize, text: &str, cursor: usize) {
         let c = text.chars().nth(cursor).unwrap();
-        let child = self
-            .children
-            .entry(c)
-            .or_insert_with(CommonPrefixTree::default);
+        let child = self.children.entry(c).or_default();
         if cursor + 1 == text.chars().count() {
             child.id = Some(id);
             return;
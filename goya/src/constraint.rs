@@ -0,0 +1,219 @@
+use super::error::{Error, Result};
+use super::id::WordIdentifier;
+use std::collections::BTreeSet;
+
+type Filter<'a> = Box<dyn Fn(&WordIdentifier) -> bool + 'a>;
+
+/// Constraints for the partial analysis like MeCab's `-p` option.
+/// All offsets are char offsets of the input text.
+/// https://taku910.github.io/mecab/partial.html
+#[derive(Default)]
+pub struct Constraints<'a> {
+    boundaries: BTreeSet<usize>,
+    spans: Vec<SpanConstraint<'a>>,
+}
+impl<'a> Constraints<'a> {
+    /// A token boundary must be at the offset
+    pub fn boundary(mut self, offset: usize) -> Self {
+        self.boundaries.insert(offset);
+        self
+    }
+
+    /// `start..end` must be one token
+    pub fn span(mut self, start: usize, end: usize) -> Self {
+        self.spans.push(SpanConstraint {
+            start,
+            end,
+            filter: None,
+        });
+        self
+    }
+
+    /// `start..end` must be one token which satisfies the filter.
+    /// When no word in the dictionary satisfies it, the span becomes an unknown word,
+    /// and when no unknown word satisfies it either, the filter is ignored so that the span is still one token.
+    pub fn span_with<F>(mut self, start: usize, end: usize, filter: F) -> Self
+    where
+        F: Fn(&WordIdentifier) -> bool + 'a,
+    {
+        self.spans.push(SpanConstraint {
+            start,
+            end,
+            filter: Some(Box::new(filter)),
+        });
+        self
+    }
+
+    /// Every span must be `start < end <= len` where `len` is the number of the chars of the text.
+    /// A span must not start with whitespace, which is skipped before a token,
+    /// and must not overlap another span or contain a boundary
+    pub fn validate<F>(&self, len: usize, is_whitespace: F) -> Result<()>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let invalid = self.spans.iter().enumerate().find(|(i, span)| {
+            span.start >= span.end
+                || span.end > len
+                || is_whitespace(span.start)
+                || self
+                    .boundaries
+                    .range((span.start + 1)..span.end)
+                    .next()
+                    .is_some()
+                || self.spans[..*i]
+                    .iter()
+                    .any(|other| other.start < span.end && span.start < other.end)
+        });
+        match invalid {
+            Some((_, span)) => Err(Error::InvalidSpan(span.start, span.end)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn span_at(&self, start: usize) -> Option<&SpanConstraint<'a>> {
+        self.spans.iter().find(|span| span.start == start)
+    }
+
+    /// Returns the max end offset of a token which starts at `start`
+    pub fn limit(&self, start: usize, len: usize) -> usize {
+        let boundary = self.boundaries.range((start + 1)..).next().copied();
+        let span = self
+            .spans
+            .iter()
+            .filter(|span| span.start > start)
+            .map(|span| span.start)
+            .min();
+        [Some(len), boundary, span]
+            .iter()
+            .flatten()
+            .copied()
+            .min()
+            .unwrap()
+    }
+
+    /// Returns true if a token `start..(start + len)` doesn't break any constraints
+    pub fn is_allowed(&self, start: usize, len: usize) -> bool {
+        let end = start + len;
+        if self.boundaries.range((start + 1)..end).next().is_some() {
+            return false;
+        }
+        self.spans.iter().all(|span| {
+            end <= span.start || span.end <= start || (span.start == start && span.end == end)
+        })
+    }
+
+    pub fn accepts(&self, start: usize, len: usize, wid: &WordIdentifier) -> bool {
+        if !self.is_allowed(start, len) {
+            return false;
+        }
+        match self.span_at(start) {
+            Some(span) => span.end == start + len && span.accepts(wid),
+            None => true,
+        }
+    }
+}
+
+pub struct SpanConstraint<'a> {
+    pub start: usize,
+    pub end: usize,
+    filter: Option<Filter<'a>>,
+}
+impl<'a> SpanConstraint<'a> {
+    pub fn accepts(&self, wid: &WordIdentifier) -> bool {
+        match &self.filter {
+            Some(filter) => filter(wid),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundary_splits_tokens() {
+        let constraints = Constraints::default().boundary(2);
+        assert!(constraints.is_allowed(0, 2));
+        assert!(constraints.is_allowed(2, 3));
+        assert!(!constraints.is_allowed(1, 2));
+        assert_eq!(constraints.limit(0, 10), 2);
+        assert_eq!(constraints.limit(2, 10), 10);
+    }
+
+    #[test]
+    fn span_must_be_one_token() {
+        let constraints = Constraints::default().span(2, 5);
+        assert!(constraints.is_allowed(0, 2));
+        assert!(constraints.is_allowed(2, 3));
+        assert!(constraints.is_allowed(5, 1));
+        assert!(!constraints.is_allowed(2, 1));
+        assert!(!constraints.is_allowed(1, 2));
+        assert!(!constraints.is_allowed(4, 2));
+        assert_eq!(constraints.limit(0, 10), 2);
+    }
+
+    #[test]
+    fn validate_spans() {
+        let validate = |constraints: Constraints| constraints.validate(5, |i| i == 3);
+        assert!(validate(Constraints::default().span(0, 2).span(2, 5).boundary(2)).is_ok());
+        assert_eq!(
+            validate(Constraints::default().span(2, 1)),
+            Err(Error::InvalidSpan(2, 1))
+        );
+        assert_eq!(
+            validate(Constraints::default().span(2, 2)),
+            Err(Error::InvalidSpan(2, 2))
+        );
+        assert_eq!(
+            validate(Constraints::default().span(1, 50)),
+            Err(Error::InvalidSpan(1, 50))
+        );
+    }
+
+    #[test]
+    fn reject_span_from_whitespace() {
+        assert_eq!(
+            Constraints::default().span(3, 5).validate(5, |i| i == 3),
+            Err(Error::InvalidSpan(3, 5))
+        );
+    }
+
+    #[test]
+    fn reject_overlapping_spans() {
+        assert_eq!(
+            Constraints::default()
+                .span(0, 3)
+                .span(2, 4)
+                .validate(5, |_| false),
+            Err(Error::InvalidSpan(2, 4))
+        );
+        assert_eq!(
+            Constraints::default()
+                .span(1, 2)
+                .span(0, 4)
+                .validate(5, |_| false),
+            Err(Error::InvalidSpan(0, 4))
+        );
+    }
+
+    #[test]
+    fn reject_boundary_in_span() {
+        assert_eq!(
+            Constraints::default()
+                .span(0, 3)
+                .boundary(1)
+                .validate(5, |_| false),
+            Err(Error::InvalidSpan(0, 3))
+        );
+    }
+
+    #[test]
+    fn span_with_filter() {
+        let constraints = Constraints::default()
+            .span_with(0, 2, |wid| matches!(wid, WordIdentifier::Known(1, _)));
+        assert!(constraints.accepts(0, 2, &WordIdentifier::Known(1, String::from("ab"))));
+        assert!(!constraints.accepts(0, 2, &WordIdentifier::Known(2, String::from("ab"))));
+        assert!(!constraints.accepts(0, 1, &WordIdentifier::Known(1, String::from("a"))));
+    }
+}
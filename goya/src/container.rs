@@ -0,0 +1,320 @@
+//! A single file which has all the sections of a compiled dictionary, e.g. the double array and the word features.
+//!
+//! The layout in little endian:
+//!
+//! - magic number `GOYADIC\0`
+//! - format version: u32
+//! - version of Goya which compiled it: string
+//! - size of the header: u32
+//! - checksum of the rest of the header: u32
+//! - source dictionary name: string
+//! - charset of the source dictionary: string
+//! - build time in seconds since the Unix epoch: u64
+//! - number of sections: u32
+//! - name: string, offset: u64, size: u64 and checksum: u32 of each section
+//! - sections, which are aligned to 16 bytes for rkyv
+//!
+//! A string is its size in bytes as u32 followed by UTF-8 bytes. The checksums are CRC-32.
+//! Loading checks only the header since the sections are large, and `Container::verify` checks the sections.
+use super::error::{Error, Result};
+use std::convert::TryInto;
+
+pub const MAGIC: &[u8; 8] = b"GOYADIC\0";
+/// Must be bumped when the layout of the container or any archive in it changes
+pub const FORMAT_VERSION: u32 = 2;
+const ALIGNMENT: usize = 16;
+
+/// Named after the kind of the double array, so that the one compiled without the same features isn't loaded
+#[cfg(not(feature = "utf8-double-array"))]
+pub const SECTION_DOUBLE_ARRAY: &str = "da";
+/// Named after the kind of the double array, so that the one compiled without the same features isn't loaded
+#[cfg(feature = "utf8-double-array")]
+pub const SECTION_DOUBLE_ARRAY: &str = "da-utf8";
+pub const SECTION_DICTIONARY: &str = "dict";
+pub const SECTION_WORD_FEATURES: &str = "features";
+pub const SECTION_USER_DICTIONARY: &str = "user";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Version of Goya which compiled the dictionary
+    pub goya_version: String,
+    /// e.g. mecab-ipadic-2.7.0-20070801
+    pub source: String,
+    /// Charset of the source dictionary, e.g. EUC-JP
+    pub charset: String,
+    /// Seconds since the Unix epoch
+    pub built_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    pub checksum: u32,
+}
+
+/// A parsed container which borrows the bytes, e.g. a memory-mapped file
+pub struct Container<'a> {
+    metadata: Metadata,
+    sections: Vec<Section>,
+    bytes: &'a [u8],
+}
+impl<'a> Container<'a> {
+    /// Parses the header and verifies its checksum. The sections are not read, so that a memory-mapped file
+    /// starts up instantly, and `Container::verify` verifies their checksums.
+    /// A container compiled by an incompatible version of Goya results in `Error::IncompatibleFormat`.
+    /// `bytes` must be aligned to 16 bytes to use the archives in it without copying them.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Container<'a>> {
+        let mut reader = Reader::from(bytes);
+        if reader.read(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a dictionary compiled by Goya"));
+        }
+        let format_version = reader.u32()?;
+        let goya_version = reader.string()?;
+        if format_version != FORMAT_VERSION {
+            return Err(Error::IncompatibleFormat(format_version, goya_version));
+        }
+        let header_len = reader.u32()? as usize;
+        let checksum = reader.u32()?;
+        let header = bytes
+            .get(reader.position..header_len)
+            .ok_or_else(|| invalid("the header is truncated"))?;
+        if crc32fast::hash(header) != checksum {
+            return Err(invalid("checksum mismatch of the header"));
+        }
+
+        let metadata = Metadata {
+            goya_version,
+            source: reader.string()?,
+            charset: reader.string()?,
+            built_at: reader.u64()?,
+        };
+        let mut sections = vec![];
+        for _ in 0..reader.u32()? {
+            sections.push(Section {
+                name: reader.string()?,
+                offset: reader.u64()? as usize,
+                len: reader.u64()? as usize,
+                checksum: reader.u32()?,
+            });
+        }
+        for section in sections.iter() {
+            if !matches!(section.offset.checked_add(section.len), Some(end) if end <= bytes.len()) {
+                return Err(invalid(&format!("section {} is truncated", section.name)));
+            }
+        }
+        Ok(Container {
+            metadata,
+            sections,
+            bytes,
+        })
+    }
+
+    /// Verifies the checksums of all the sections, which reads the whole file
+    pub fn verify(&self) -> Result<()> {
+        for section in self.sections.iter() {
+            let body = &self.bytes[section.offset..section.offset + section.len];
+            if crc32fast::hash(body) != section.checksum {
+                return Err(invalid(&format!(
+                    "checksum mismatch of section {}",
+                    section.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn section(&self, name: &str) -> Option<&'a [u8]> {
+        self.sections
+            .iter()
+            .find(|section| section.name == name)
+            .map(|section| &self.bytes[section.offset..section.offset + section.len])
+    }
+
+    /// Same as `Container::section` but a missing section is an error
+    pub fn required_section(&self, name: &str) -> Result<&'a [u8]> {
+        self.section(name)
+            .ok_or_else(|| invalid(&format!("section {} is not found", name)))
+    }
+}
+
+/// Builds a container of the sections in the given order
+pub fn to_bytes(metadata: &Metadata, sections: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut header = vec![];
+    write_string(&mut header, &metadata.source);
+    write_string(&mut header, &metadata.charset);
+    header.extend_from_slice(&metadata.built_at.to_le_bytes());
+    header.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+
+    let mut prefix = vec![];
+    prefix.extend_from_slice(MAGIC);
+    prefix.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_string(&mut prefix, &metadata.goya_version);
+    // The size of the header and its checksum
+    let fixed_len = prefix.len() + 8;
+    let table_len: usize = sections.iter().map(|(name, _)| 4 + name.len() + 20).sum();
+    let mut offset = align(fixed_len + header.len() + table_len);
+    for (name, body) in sections {
+        write_string(&mut header, name);
+        header.extend_from_slice(&(offset as u64).to_le_bytes());
+        header.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        header.extend_from_slice(&crc32fast::hash(body).to_le_bytes());
+        offset = align(offset + body.len());
+    }
+
+    let mut bytes = prefix;
+    bytes.extend_from_slice(&((fixed_len + header.len()) as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&header).to_le_bytes());
+    bytes.append(&mut header);
+    for (_, body) in sections {
+        bytes.resize(align(bytes.len()), 0);
+        bytes.extend_from_slice(body);
+    }
+    bytes
+}
+
+fn align(len: usize) -> usize {
+    len.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    bytes.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidContainer(reason.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn from(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.read(len)?.to_vec()).map_err(|_| invalid("malformed string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            goya_version: String::from("0.1.9"),
+            source: String::from("mini-ipadic"),
+            charset: String::from("EUC-JP"),
+            built_at: 1_600_000_000,
+        }
+    }
+
+    fn container() -> Vec<u8> {
+        to_bytes(
+            &metadata(),
+            &[
+                (SECTION_DOUBLE_ARRAY, b"double array"),
+                (SECTION_DICTIONARY, b""),
+                (SECTION_WORD_FEATURES, b"features"),
+            ],
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = container();
+        let container = Container::from_bytes(&bytes).unwrap();
+        assert_eq!(container.metadata(), &metadata());
+        assert_eq!(
+            container.section(SECTION_DOUBLE_ARRAY),
+            Some(&b"double array"[..])
+        );
+        assert_eq!(container.section(SECTION_DICTIONARY), Some(&b""[..]));
+        assert_eq!(
+            container.section(SECTION_WORD_FEATURES),
+            Some(&b"features"[..])
+        );
+        assert_eq!(container.section(SECTION_USER_DICTIONARY), None);
+        assert!(matches!(
+            container.required_section(SECTION_USER_DICTIONARY),
+            Err(Error::InvalidContainer(_))
+        ));
+        assert!(container
+            .sections()
+            .iter()
+            .all(|section| section.offset % ALIGNMENT == 0));
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert!(matches!(
+            Container::from_bytes(b"not a dictionary"),
+            Err(Error::InvalidContainer(_))
+        ));
+        assert!(matches!(
+            Container::from_bytes(&[]),
+            Err(Error::InvalidContainer(_))
+        ));
+    }
+
+    #[test]
+    fn reject_incompatible_format() {
+        let mut bytes = container();
+        bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Container::from_bytes(&bytes).err(),
+            Some(Error::IncompatibleFormat(
+                FORMAT_VERSION + 1,
+                String::from("0.1.9")
+            ))
+        );
+    }
+
+    #[test]
+    fn reject_broken_sections() {
+        let mut bytes = container();
+        assert!(Container::from_bytes(&bytes).unwrap().verify().is_ok());
+        let len = bytes.len();
+        bytes[len - 1] ^= 1;
+        // The sections are verified only on demand
+        let broken = Container::from_bytes(&bytes).unwrap();
+        assert!(matches!(broken.verify(), Err(Error::InvalidContainer(_))));
+
+        let bytes = container();
+        assert!(matches!(
+            Container::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidContainer(_))
+        ));
+    }
+}
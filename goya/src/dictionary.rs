@@ -1,20 +1,92 @@
-use super::char_class::CharDefinition;
+use super::char_class::CharDefinitionRef;
+use super::error::{Error, Result};
 use super::id::WordIdentifier;
 use super::morpheme::Morpheme;
+use super::user_dictionary::UserDictionary;
+use rkyv::Archived;
 
+/// IDs of words borrowed from a dictionary, which both of the owned and the archived dictionaries can return
+#[derive(Debug, Clone, Copy)]
+pub enum WordIds<'a> {
+    Owned(&'a [usize]),
+    Archived(&'a [Archived<usize>]),
+}
+impl<'a> WordIds<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = usize> + 'a {
+        let (owned, archived) = match *self {
+            WordIds::Owned(ids) => (ids, &[][..]),
+            WordIds::Archived(ids) => (&[][..], ids),
+        };
+        owned
+            .iter()
+            .copied()
+            .chain(archived.iter().map(|id| *id as usize))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            WordIds::Owned(ids) => ids.len(),
+            WordIds::Archived(ids) => ids.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Morphemes are returned by value so that archived dictionaries can implement it without deserialization
 pub trait Dictionary {
-    fn get(&self, wid: &WordIdentifier) -> Option<&Morpheme> {
+    fn get(&self, wid: &WordIdentifier) -> Result<Morpheme> {
         match wid {
             WordIdentifier::Known(wid, _) => self.get_known_morpheme(wid),
             WordIdentifier::Unknown(wid, _) => self.get_unknown_morpheme(wid),
+            WordIdentifier::User(wid, _) => self
+                .user_dictionary()
+                .ok_or(Error::UserMorphemeNotFound(*wid))?
+                .get(wid)
+                .copied(),
         }
     }
-    fn get_known_morpheme(&self, wid: &usize) -> Option<&Morpheme>;
-    fn get_unknown_morpheme(&self, wid: &usize) -> Option<&Morpheme>;
-    fn resolve_homonyms(&self, wid: &usize) -> Option<&Vec<usize>>;
-    fn take_unknown_chars_seq(&self, def: &CharDefinition, text: &str, start: &usize) -> String;
-    fn classify_char(&self, c: &char) -> &CharDefinition;
-    fn get_unknown_morphemes_by_class(&self, class: &str) -> Vec<(usize, &Morpheme)>;
-    fn transition_cost(&self, left: &usize, right: &usize) -> Option<&i16>;
-    fn occurrence_cost(&self, wid: &usize) -> Option<i16>;
+    fn get_known_morpheme(&self, wid: &usize) -> Result<Morpheme>;
+    fn get_unknown_morpheme(&self, wid: &usize) -> Result<Morpheme>;
+    /// `id` is a value of the double array. Returns the IDs of the known words which have the surface form
+    fn resolve_homonyms(&self, id: &usize) -> Result<WordIds<'_>>;
+    fn unknown_word_lengths(&self, text: &str, start: &usize, end: &usize) -> Result<Vec<usize>>;
+    fn classify_char(&self, c: &char) -> Result<CharDefinitionRef<'_>>;
+    fn get_unknown_morphemes_by_class(&self, class: &str) -> Result<Vec<(usize, Morpheme)>>;
+    fn transition_cost(&self, left: &usize, right: &usize) -> Result<i16>;
+    fn occurrence_cost(&self, wid: &usize) -> Result<i16>;
+    fn user_dictionary(&self) -> Option<&UserDictionary> {
+        None
+    }
+}
+impl<D: Dictionary + ?Sized> Dictionary for &D {
+    fn get_known_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        (**self).get_known_morpheme(wid)
+    }
+    fn get_unknown_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        (**self).get_unknown_morpheme(wid)
+    }
+    fn resolve_homonyms(&self, id: &usize) -> Result<WordIds<'_>> {
+        (**self).resolve_homonyms(id)
+    }
+    fn unknown_word_lengths(&self, text: &str, start: &usize, end: &usize) -> Result<Vec<usize>> {
+        (**self).unknown_word_lengths(text, start, end)
+    }
+    fn classify_char(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+        (**self).classify_char(c)
+    }
+    fn get_unknown_morphemes_by_class(&self, class: &str) -> Result<Vec<(usize, Morpheme)>> {
+        (**self).get_unknown_morphemes_by_class(class)
+    }
+    fn transition_cost(&self, left: &usize, right: &usize) -> Result<i16> {
+        (**self).transition_cost(left, right)
+    }
+    fn occurrence_cost(&self, wid: &usize) -> Result<i16> {
+        (**self).occurrence_cost(wid)
+    }
+    fn user_dictionary(&self) -> Option<&UserDictionary> {
+        (**self).user_dictionary()
+    }
 }
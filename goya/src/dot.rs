@@ -27,7 +27,7 @@ pub fn render<D: Dictionary>(lattice: &Lattice, dict: &D) -> Result<String, Box<
     )?;
     for (i, index) in lattice.indices.iter().enumerate() {
         for (j, (left_wid, wlen)) in index.iter().enumerate() {
-            let left = dict.get(left_wid).unwrap();
+            let left = dict.get(left_wid)?;
             let node_style = match &best_path {
                 Some(best_path) if best_path.contains(&(i + 1, j)) => BOLD,
                 _ => "",
@@ -44,9 +44,7 @@ pub fn render<D: Dictionary>(lattice: &Lattice, dict: &D) -> Result<String, Box<
             )?;
             if i == 0 {
                 let right = left;
-                let cost = dict
-                    .transition_cost(&BOS_CONTEXT_ID, &right.right_context_id)
-                    .unwrap();
+                let cost = dict.transition_cost(&BOS_CONTEXT_ID, &right.left_context_id)?;
                 let bos_edge_style = match &best_path {
                     Some(best_path) if best_path.contains(&(i + 1, j)) => BOLD,
                     _ => "",
@@ -58,9 +56,7 @@ pub fn render<D: Dictionary>(lattice: &Lattice, dict: &D) -> Result<String, Box<
                 )?;
             }
             if i + wlen >= len {
-                let cost = dict
-                    .transition_cost(&left.left_context_id, &EOS_CONTEXT_ID)
-                    .unwrap();
+                let cost = dict.transition_cost(&left.right_context_id, &EOS_CONTEXT_ID)?;
                 let eos_edge_style = match &best_path {
                     Some(best_path) if best_path.contains(&(i + 1, j)) => BOLD,
                     _ => "",
@@ -73,10 +69,8 @@ pub fn render<D: Dictionary>(lattice: &Lattice, dict: &D) -> Result<String, Box<
                 continue;
             }
             for (k, (right_wid, _)) in lattice.indices[i + wlen].iter().enumerate() {
-                let right = dict.get(right_wid).unwrap();
-                let cost = dict
-                    .transition_cost(&left.left_context_id, &right.right_context_id)
-                    .unwrap();
+                let right = dict.get(right_wid)?;
+                let cost = dict.transition_cost(&left.right_context_id, &right.left_context_id)?;
                 let edge_style = match &best_path {
                     Some(best_path)
                         if best_path.contains(&(i + 1, j))
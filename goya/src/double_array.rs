@@ -1,11 +1,12 @@
 use super::common_prefix_tree::CommonPrefixTree;
 use indexmap::IndexSet;
-use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::cmp;
 use std::collections::HashMap;
+use std::ops::Range;
+use std::str::Chars;
 
-const INDEX_ROOT: usize = 1;
+pub(crate) const INDEX_ROOT: usize = 1;
 const TERM_CHAR: char = '\0';
 
 #[derive(Debug)]
@@ -18,7 +19,195 @@ pub enum TransitionError {
     CheckOutOfBounds,
 }
 
+/// Lookups of a double array. The archived double arrays implement it as well,
+/// so that a memory-mapped dictionary can be used without deserialization
+pub trait Trie {
+    /// Returns the index of the node after `to` from the node at `from`
+    fn transition(&self, from: usize, to: char) -> Result<(i32, Option<usize>), TransitionError>;
+    /// Returns the ID of the key which ends at the node
+    fn stop(&self, from: usize) -> Result<usize, TransitionError>;
+    /// Returns `(char, index)` of the children of the node in the order of the chars
+    fn children(&self, index: usize) -> Vec<(char, usize)>;
+
+    fn init(&self, to: char) -> Result<(i32, Option<usize>), TransitionError> {
+        self.transition(INDEX_ROOT, to)
+    }
+
+    /// Returns `(length in chars, ID)` of the keys which are prefixes of `text`, from the shortest one
+    fn common_prefix_search<'a>(&'a self, text: &'a str) -> CommonPrefixSearch<'a, Self>
+    where
+        Self: Sized,
+    {
+        CommonPrefixSearch {
+            da: self,
+            chars: text.chars(),
+            cursor: Some(INDEX_ROOT),
+            len: 0,
+        }
+    }
+
+    /// Returns `(key, ID)` of the keys which start with `prefix` in the order of the keys.
+    /// The children of a node are found from the base and the check without any other table,
+    /// so `entries` of the double arrays is faster to list all the keys
+    fn predictive_search<'a>(&'a self, prefix: &str) -> PredictiveSearch<'a, Self>
+    where
+        Self: Sized,
+    {
+        let mut cursor = Some(INDEX_ROOT);
+        for c in prefix.chars() {
+            cursor = cursor.and_then(|from| self.transition(from, c).ok().map(|(t, _)| t as usize));
+        }
+        PredictiveSearch {
+            da: self,
+            open: cursor
+                .map(|index| (index, prefix.to_string()))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+impl<T: Trie + ?Sized> Trie for &T {
+    fn transition(&self, from: usize, to: char) -> Result<(i32, Option<usize>), TransitionError> {
+        (**self).transition(from, to)
+    }
+
+    fn stop(&self, from: usize) -> Result<usize, TransitionError> {
+        (**self).stop(from)
+    }
+
+    fn children(&self, index: usize) -> Vec<(char, usize)> {
+        (**self).children(index)
+    }
+}
+
+/// Iterator of `Trie::common_prefix_search`
+pub struct CommonPrefixSearch<'a, T> {
+    da: &'a T,
+    chars: Chars<'a>,
+    /// None after a transition fails
+    cursor: Option<usize>,
+    len: usize,
+}
+impl<'a, T: Trie> Iterator for CommonPrefixSearch<'a, T> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(from) = self.cursor {
+            self.cursor = self
+                .chars
+                .next()
+                .and_then(|c| self.da.transition(from, c).ok())
+                .map(|(t, _)| t as usize);
+            self.len += 1;
+            if let Some(id) = self.cursor.and_then(|t| self.da.stop(t).ok()) {
+                return Some((self.len, id));
+            }
+        }
+        None
+    }
+}
+
+/// Iterator of `Trie::predictive_search`
+pub struct PredictiveSearch<'a, T> {
+    da: &'a T,
+    /// (index, key) of the nodes to visit in the depth-first order
+    open: Vec<(usize, String)>,
+}
+impl<'a, T: Trie> Iterator for PredictiveSearch<'a, T> {
+    type Item = (String, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((index, key)) = self.open.pop() {
+            // Children are pushed in the reverse order to visit them in the order of the chars
+            for (c, child) in self.da.children(index).into_iter().rev() {
+                self.open.push((child, concat_char_to_str(&key, c)));
+            }
+            if let Ok(id) = self.da.stop(index) {
+                return Some((key, id));
+            }
+        }
+        None
+    }
+}
+
+/// Cells of the double array over the chars, which the archived one shares
+trait Cells {
+    fn get_code(&self, c: &char) -> Option<usize>;
+    fn get_base(&self, index: usize) -> Option<i32>;
+    fn get_check(&self, index: usize) -> Option<usize>;
+    fn get_char(&self, code: usize) -> Option<char>;
+    /// The number of the codes, i.e. the chars in the keys and the terminator
+    fn code_len(&self) -> usize;
+}
+
+fn transition<T: Cells>(
+    da: &T,
+    from: usize,
+    to: char,
+) -> Result<(i32, Option<usize>), TransitionError> {
+    // NUL in the text must not be confused with the end of a word
+    if to == TERM_CHAR {
+        return Err(TransitionError::UnknownChar);
+    }
+    transition_raw(da, from, to)
+}
+
+fn stop<T: Cells>(da: &T, from: usize) -> Result<usize, TransitionError> {
+    match transition_raw(da, from, TERM_CHAR) {
+        Ok((_, Some(wid))) => Ok(wid),
+        Ok(_) => unreachable!("Successful transition, but no wid"),
+        Err(reason) => Err(reason),
+    }
+}
+
+fn children<T: Cells>(da: &T, index: usize) -> Vec<(char, usize)> {
+    let base = match da.get_base(index) {
+        Some(base) if base >= 0 => base as usize,
+        _ => return vec![],
+    };
+    // The children are the indices after the base whose check is the node, and the codes are sorted like the chars.
+    // The indices out of the check can't be children
+    (base + 1..base + da.code_len())
+        .map_while(|t| da.get_check(t).map(|check| (t, check)))
+        .filter(|(_, check)| *check == index)
+        .filter_map(|(t, _)| da.get_char(t - base).map(|c| (c, t)))
+        .collect()
+}
+
+fn transition_raw<T: Cells>(
+    da: &T,
+    from: usize,
+    to: char,
+) -> Result<(i32, Option<usize>), TransitionError> {
+    let code = da.get_code(&to).ok_or(TransitionError::UnknownChar)?;
+    let s = da.get_base(from).ok_or(TransitionError::BaseOutOfBounds)?;
+    let t = s + code as i32;
+    if t < 0 {
+        return Err(TransitionError::AlreadyTerminated);
+    }
+    let next = da
+        .get_check(as_usize(&t))
+        .ok_or(TransitionError::CheckOutOfBounds)?;
+    let base = da.get_base(t as usize).ok_or(TransitionError::BaseFailed)?;
+    let wid = if base < 0 { Some(-base as usize) } else { None };
+    if next == from {
+        Ok((t, wid))
+    } else {
+        Err(TransitionError::CheckFailed)
+    }
+}
+
+/// The double array of the system dictionary. The `utf8-double-array` feature switches it to `Utf8DoubleArray`,
+/// which is experimental. See `Utf8DoubleArray` for the trade-off
+#[cfg(not(feature = "utf8-double-array"))]
+pub type SystemDoubleArray = DoubleArray;
+/// The double array of the system dictionary. The `utf8-double-array` feature switches it to `Utf8DoubleArray`,
+/// which is experimental. See `Utf8DoubleArray` for the trade-off
+#[cfg(feature = "utf8-double-array")]
+pub type SystemDoubleArray = super::utf8_double_array::Utf8DoubleArray;
+
 #[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
 pub struct DoubleArray {
     pub codes: IndexSet<char>,
     pub base: Vec<i32>,
@@ -35,6 +224,74 @@ impl Default for DoubleArray {
         DoubleArray { base, check, codes }
     }
 }
+impl Cells for DoubleArray {
+    fn get_code(&self, c: &char) -> Option<usize> {
+        self.codes.get_index_of(c)
+    }
+
+    fn get_base(&self, index: usize) -> Option<i32> {
+        self.base.get(index).copied()
+    }
+
+    fn get_check(&self, index: usize) -> Option<usize> {
+        self.check.get(index).copied()
+    }
+
+    fn get_char(&self, code: usize) -> Option<char> {
+        self.codes.get_index(code).copied()
+    }
+
+    fn code_len(&self) -> usize {
+        self.codes.len()
+    }
+}
+impl Cells for ArchivedDoubleArray {
+    fn get_code(&self, c: &char) -> Option<usize> {
+        self.codes.get_index_of(c)
+    }
+
+    fn get_base(&self, index: usize) -> Option<i32> {
+        self.base.get(index).copied()
+    }
+
+    fn get_check(&self, index: usize) -> Option<usize> {
+        self.check.get(index).map(|check| *check as usize)
+    }
+
+    fn get_char(&self, code: usize) -> Option<char> {
+        self.codes.get_index(code).copied()
+    }
+
+    fn code_len(&self) -> usize {
+        self.codes.len()
+    }
+}
+impl Trie for DoubleArray {
+    fn transition(&self, from: usize, to: char) -> Result<(i32, Option<usize>), TransitionError> {
+        transition(self, from, to)
+    }
+
+    fn stop(&self, from: usize) -> Result<usize, TransitionError> {
+        stop(self, from)
+    }
+
+    fn children(&self, index: usize) -> Vec<(char, usize)> {
+        children(self, index)
+    }
+}
+impl Trie for ArchivedDoubleArray {
+    fn transition(&self, from: usize, to: char) -> Result<(i32, Option<usize>), TransitionError> {
+        transition(self, from, to)
+    }
+
+    fn stop(&self, from: usize) -> Result<usize, TransitionError> {
+        stop(self, from)
+    }
+
+    fn children(&self, index: usize) -> Vec<(char, usize)> {
+        children(self, index)
+    }
+}
 impl DoubleArray {
     pub fn from(base: Vec<i32>, check: Vec<usize>, codes: IndexSet<char>) -> Self {
         DoubleArray { base, check, codes }
@@ -47,162 +304,274 @@ impl DoubleArray {
             .map(|s| as_usize(&(s * -1)))
     }
 
+    /// Same as `DoubleArray::from_sorted_keys` with the words in the tree
     pub fn from_cpt(trie: &CommonPrefixTree) -> Self {
-        let mut state_cache = HashMap::new();
+        let mut keys = vec![];
+        collect_keys(trie, &mut String::new(), &mut keys);
+        DoubleArray::from_sorted_keys(keys.iter().map(|(key, id)| (key.as_str(), *id as u32)))
+    }
+
+    /// Builds the double array from the keys sorted like `str::cmp` and their IDs without any intermediate tree.
+    /// The keys are streamed into a flat buffer, so the memory usage is linear to the total length of them.
+    ///
+    /// # Panics
+    ///
+    /// The keys must be sorted and unique, and the IDs must be in `1..=i32::MAX` since a negative base means an ID.
+    /// A key must not contain NUL since it's the end of a key.
+    pub fn from_sorted_keys<'a, I>(keys: I) -> Self
+    where
+        I: Iterator<Item = (&'a str, u32)>,
+    {
+        let keys = keys
+            .inspect(|(key, _)| assert!(!key.contains(TERM_CHAR), "key {:?} contains NUL", key));
+        let keys = Keys::from_sorted(keys, str::chars);
+        let mut codes = keys.units.clone();
+        codes.sort_unstable();
+        codes.dedup();
         let mut da = DoubleArray::default();
-        let mut chars = trie
-            .entires_dfs()
-            .iter()
-            .map(|(prefix, _)| prefix)
-            .join("")
-            .chars()
-            .collect::<Vec<_>>();
-        chars.sort_unstable();
-        chars.dedup();
-        for c in chars {
-            da.insert_to_codes(c);
-        }
+        da.codes.extend(codes);
+        da.codes.shrink_to_fit();
+        let (base, check) = Builder::build(&keys, |c| da.codes.get_index_of(c).unwrap());
+        da.base = base;
+        da.check = check;
+        da
+    }
 
-        for (prefix, node) in trie.entires_dfs() {
-            if node.can_stop() {
-                continue;
+    /// Returns all the words and their IDs in the order of the words
+    pub fn entries(&self) -> Vec<(String, usize)> {
+        let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, parent) in self.check.iter().enumerate() {
+            if *parent != 0 {
+                children.entry(*parent).or_default().push(index);
             }
-
-            // root node
-            if prefix.is_empty() {
-                for next_c in node.children.keys() {
-                    let next_char_code = da.get_code(next_c).unwrap();
-                    let t = da.base[INDEX_ROOT] + next_char_code as i32;
-                    let t = as_usize(&t);
-                    da.insert_to_check(t, INDEX_ROOT);
-                    state_cache.insert(concat_char_to_str(&prefix, *next_c), t);
+        }
+        let mut entries = vec![];
+        let mut open = vec![(INDEX_ROOT, String::new())];
+        while let Some((parent, prefix)) = open.pop() {
+            for index in children.get(&parent).into_iter().flatten() {
+                let code = *index as i32 - self.base[parent];
+                match self.codes.get_index(code as usize) {
+                    Some(&TERM_CHAR) if self.base[*index] < 0 => {
+                        entries.push((prefix.clone(), as_usize(&-self.base[*index])))
+                    }
+                    Some(&TERM_CHAR) | None => {}
+                    Some(c) => open.push((*index, concat_char_to_str(&prefix, *c))),
                 }
-                continue;
             }
+        }
+        entries.sort();
+        entries
+    }
+}
 
-            let s = *state_cache.get(&prefix).unwrap();
-            da.insert_to_base(s, da.find_next_s(node));
-            for (next_c, child) in node.children.iter() {
-                let t = da.base.get(s).unwrap() + da.get_code(next_c).unwrap() as i32;
-                let t = as_usize(&t);
-                da.insert_to_check(t, s);
-                if child.can_stop() {
-                    da.insert_to_base(t, -(child.id.unwrap() as i32));
-                } else {
-                    let key = concat_char_to_str(&prefix, *next_c);
-                    state_cache.insert(key, t);
-                }
+/// Collects the words in the sorted order since the children of a tree are sorted
+fn collect_keys(trie: &CommonPrefixTree, prefix: &mut String, keys: &mut Vec<(String, usize)>) {
+    for (c, child) in trie.children.iter() {
+        match (*c, child.id) {
+            (TERM_CHAR, Some(id)) => keys.push((prefix.clone(), id)),
+            (TERM_CHAR, None) => {}
+            (c, _) => {
+                prefix.push(c);
+                collect_keys(child, prefix, keys);
+                prefix.pop();
             }
         }
-        da.base.shrink_to_fit();
-        da.check.shrink_to_fit();
-        da.codes.shrink_to_fit();
-        da
     }
+}
 
-    pub fn transition(
-        &self,
-        from: usize,
-        to: char,
-    ) -> Result<(i32, Option<usize>), TransitionError> {
-        let code = self.get_code(&to).ok_or(TransitionError::UnknownChar)?;
-        let s = self
-            .base
-            .get(from)
-            .ok_or(TransitionError::BaseOutOfBounds)?;
-        let t = s + code as i32;
-        if t < 0 {
-            return Err(TransitionError::AlreadyTerminated);
-        }
-        let next = self
-            .check
-            .get(as_usize(&t))
-            .ok_or(TransitionError::CheckOutOfBounds)?;
-        let base = self
-            .base
-            .get(t as usize)
-            .ok_or(TransitionError::BaseFailed)?;
-        let wid = if *base < 0 {
-            Some((base * -1) as usize)
-        } else {
-            None
+/// Units of all the keys in a buffer, e.g. chars or UTF-8 bytes
+pub(crate) struct Keys<U> {
+    units: Vec<U>,
+    /// index = key, value = end of the key in `units`
+    ends: Vec<usize>,
+    ids: Vec<usize>,
+}
+impl<U: Ord> Keys<U> {
+    /// Streams the keys sorted like `str::cmp` and their IDs into the buffer.
+    ///
+    /// # Panics
+    ///
+    /// The keys must be sorted and unique, and the IDs must be in `1..=i32::MAX` since a negative base means an ID.
+    pub(crate) fn from_sorted<'a, I, F, K>(keys: I, units: F) -> Self
+    where
+        I: Iterator<Item = (&'a str, u32)>,
+        F: Fn(&'a str) -> K,
+        K: Iterator<Item = U>,
+    {
+        let mut buffer = Keys {
+            units: vec![],
+            ends: vec![],
+            ids: vec![],
         };
-        if *next == from {
-            Ok((t, wid))
-        } else {
-            Err(TransitionError::CheckFailed)
+        for (key, id) in keys {
+            assert!(
+                0 < id && id <= i32::MAX as u32,
+                "id({}) of {} is out of range",
+                id,
+                key
+            );
+            buffer.units.extend(units(key));
+            buffer.ends.push(buffer.units.len());
+            buffer.ids.push(id as usize);
+            let len = buffer.len();
+            assert!(
+                len < 2 || buffer.get(len - 2) < buffer.get(len - 1),
+                "{} is not sorted or not unique",
+                key
+            );
         }
+        buffer
     }
 
-    pub fn init(&self, to: char) -> Result<(i32, Option<usize>), TransitionError> {
-        self.transition(INDEX_ROOT, to)
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn get(&self, idx: usize) -> &[U] {
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        &self.units[start..self.ends[idx]]
     }
+}
 
-    pub fn stop(&self, from: usize) -> Result<usize, TransitionError> {
-        match self.transition(from, TERM_CHAR) {
-            Ok((_, Some(wid))) => Ok(wid),
-            Ok(_) => unreachable!("Successful transition, but no wid"),
-            Err(reason) => Err(reason),
+/// Places the nodes from the root in the depth-first order.
+/// The unused indices are linked to each other, so finding a base doesn't scan the used ones.
+pub(crate) struct Builder<'a, U, F> {
+    keys: &'a Keys<U>,
+    /// Code of a unit, which must be sorted like the units. 0 is the end of a key
+    code: F,
+    base: Vec<i32>,
+    check: Vec<usize>,
+    /// Circular doubly linked list of the unused indices. Index 0 is the sentinel since it's never used
+    next_free: Vec<usize>,
+    prev_free: Vec<usize>,
+    /// The unused index to start finding a base from. The ones before it are skipped like Darts
+    /// since they are in an area that is almost full
+    start: usize,
+}
+impl<'a, U: Ord, F: Fn(&U) -> usize> Builder<'a, U, F> {
+    /// Returns the base and the check
+    pub(crate) fn build(keys: &'a Keys<U>, code: F) -> (Vec<i32>, Vec<usize>) {
+        let mut builder = Builder {
+            keys,
+            code,
+            base: vec![0, 1],
+            check: vec![0, 0],
+            next_free: vec![0, 0],
+            prev_free: vec![0, 0],
+            start: 0,
+        };
+        let mut open = vec![(0..keys.len(), 0, INDEX_ROOT)];
+        while let Some((range, depth, index)) = open.pop() {
+            let siblings = builder.siblings(range, depth);
+            if siblings.is_empty() {
+                continue;
+            }
+            let base = builder.find_base(&siblings);
+            builder.base[index] = base as i32;
+            // Children are pushed in the reverse order to place them in the order of the keys
+            for (code, range) in siblings.into_iter().rev() {
+                let t = base + code;
+                builder.use_index(t, index);
+                if code == 0 {
+                    let id = builder.keys.ids[range.start];
+                    builder.base[t] = -(id as i32);
+                } else {
+                    open.push((range, depth + 1, t));
+                }
+            }
         }
+        builder.shrink();
+        (builder.base, builder.check)
     }
 
-    pub fn get_code(&self, c: &char) -> Option<usize> {
-        self.codes.get_full(c).map(|(code, _)| code)
+    /// (code, range of the keys) of the children, which are sorted by the codes
+    fn siblings(&self, range: Range<usize>, depth: usize) -> Vec<(usize, Range<usize>)> {
+        let mut siblings: Vec<(usize, Range<usize>)> = vec![];
+        for idx in range {
+            let code = self.keys.get(idx).get(depth).map_or(0, &self.code);
+            match siblings.last_mut() {
+                Some((last, range)) if *last == code => range.end = idx + 1,
+                _ => siblings.push((code, idx..idx + 1)),
+            }
+        }
+        siblings
     }
 
-    fn insert_to_codes(&mut self, c: char) -> usize {
-        let (char_code, _) = self.codes.insert_full(c);
-        char_code
+    fn find_base(&mut self, siblings: &[(usize, Range<usize>)]) -> usize {
+        let first = siblings[0].0;
+        let last = siblings[siblings.len() - 1].0;
+        let mut position = self.start;
+        let mut tried = 0;
+        let base = loop {
+            // No unused index is left, so the array is extended
+            if position == 0 {
+                position = cmp::max(self.check.len(), first + 1);
+            }
+            // base must be positive since a negative one means a word ID
+            if position > first {
+                let base = position - first;
+                self.reserve(base + last + 1);
+                if siblings.iter().all(|(code, _)| self.is_free(base + code)) {
+                    break base;
+                }
+            }
+            tried += 1;
+            position = self.next_free[position];
+        };
+        let start = if self.start == 0 {
+            position
+        } else {
+            self.start
+        };
+        if tried as f64 / (position - start + 1) as f64 <= 0.05 {
+            self.start = position;
+        }
+        base
     }
 
-    fn insert_to_base(&mut self, index: usize, value: i32) {
-        let resized = cmp::max(self.base.len(), index + 1);
-        self.base.resize(resized, 0);
-        assert_eq!(
-            self.base[index], 0,
-            "index={} already used: {:?}",
-            index, self.base
-        );
-        self.base[index] = value;
+    fn is_free(&self, index: usize) -> bool {
+        index > INDEX_ROOT && self.check[index] == 0
     }
 
-    fn insert_to_check(&mut self, index: usize, value: usize) {
-        let resized = cmp::max(self.check.len(), index + 1);
-        self.check.resize(resized, 0);
-        self.check[index] = value;
+    fn reserve(&mut self, len: usize) {
+        for index in self.check.len()..len {
+            self.base.push(0);
+            self.check.push(0);
+            // Appended to the tail of the list
+            let tail = self.prev_free[0];
+            self.next_free.push(0);
+            self.prev_free.push(tail);
+            self.next_free[tail] = index;
+            self.prev_free[0] = index;
+            if self.start == 0 {
+                self.start = index;
+            }
+        }
     }
 
-    fn get_available_check_index(&self, left: usize) -> usize {
-        self.check
-            .iter()
-            .enumerate()
-            .skip(left)
-            // clippy says that `find is prefered to skip_while+next` but it's slower than the current
-            .skip_while(|(_, value)| value != &&0)
-            .next()
-            .map(|(i, _)| i)
-            .unwrap_or_else(|| unreachable!("index must be found"))
-    }
-
-    fn find_next_s(&self, child: &CommonPrefixTree) -> i32 {
-        let mut position = self.get_available_check_index(INDEX_ROOT + 1);
-        let min_code = self.get_code(child.min_char().unwrap()).unwrap();
-        let offsets: Vec<_> = child
-            .children
-            .keys()
-            .map(|c| self.get_code(c).unwrap() - min_code)
-            .collect();
-        while offsets
-            .iter()
-            .any(|code| match self.check.get(position + code) {
-                Some(0) => false,
-                Some(_) => true,
-                _ => false,
-            })
-        {
-            position += 1;
+    fn use_index(&mut self, index: usize, parent: usize) {
+        self.check[index] = parent;
+        if index == self.start {
+            self.start = self.next_free[index];
         }
-        (position - min_code) as i32
+        let (prev, next) = (self.prev_free[index], self.next_free[index]);
+        self.next_free[prev] = next;
+        self.prev_free[next] = prev;
+    }
+
+    /// Trailing unused indices are unnecessary since transitions out of bounds fail
+    fn shrink(&mut self) {
+        let len = cmp::max(
+            self.check
+                .iter()
+                .rposition(|c| *c != 0)
+                .map_or(0, |i| i + 1),
+            INDEX_ROOT + 1,
+        );
+        self.base.truncate(len);
+        self.check.truncate(len);
+        self.base.shrink_to_fit();
+        self.check.shrink_to_fit();
     }
 }
 
@@ -216,3 +585,190 @@ fn concat_char_to_str(text: &str, c: char) -> String {
     tmp.push(c);
     tmp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let mut cpt = CommonPrefixTree::default();
+        cpt.append(1, "あい");
+        cpt.append(2, "あ");
+        cpt.append(3, "いう");
+        let da = DoubleArray::from_cpt(&cpt);
+        assert_eq!(
+            da.entries(),
+            vec![
+                (String::from("あ"), 2),
+                (String::from("あい"), 1),
+                (String::from("いう"), 3)
+            ]
+        );
+    }
+
+    fn lookup(da: &DoubleArray, word: &str) -> Option<usize> {
+        let mut chars = word.chars();
+        let (mut index, _) = da.init(chars.next()?).ok()?;
+        for c in chars {
+            index = da.transition(index as usize, c).ok()?.0;
+        }
+        da.stop(index as usize).ok()
+    }
+
+    #[test]
+    fn from_cpt_finds_all_words() {
+        let words = [
+            "あ",
+            "あい",
+            "あいう",
+            "い",
+            "いう",
+            "う",
+            "か",
+            "かき",
+            "き",
+            "亜",
+            "亜い",
+            "ア",
+        ];
+        let mut cpt = CommonPrefixTree::default();
+        for (id, word) in words.iter().enumerate() {
+            cpt.append(id + 1, word);
+        }
+        let da = DoubleArray::from_cpt(&cpt);
+        for (id, word) in words.iter().enumerate() {
+            assert_eq!(lookup(&da, word), Some(id + 1), "{}", word);
+        }
+        for word in ["", "え", "いあ", "かきく", "亜亜", "\0"] {
+            assert_eq!(lookup(&da, word), None, "{}", word);
+        }
+        let mut wids = da.wids().collect::<Vec<_>>();
+        wids.sort_unstable();
+        assert_eq!(wids, (1..=words.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn from_cpt_without_words() {
+        let da = DoubleArray::from_cpt(&CommonPrefixTree::default());
+        assert!(da.init('あ').is_err());
+        assert_eq!(da.entries(), vec![]);
+    }
+
+    #[test]
+    fn from_sorted_keys() {
+        let words = ["あ", "あい", "い", "いう", "亜"];
+        let da = DoubleArray::from_sorted_keys(words.iter().zip(1..).map(|(w, id)| (*w, id)));
+        for (id, word) in words.iter().enumerate() {
+            assert_eq!(lookup(&da, word), Some(id + 1), "{}", word);
+        }
+
+        let mut cpt = CommonPrefixTree::default();
+        for (id, word) in words.iter().enumerate() {
+            cpt.append(id + 1, word);
+        }
+        let expected = DoubleArray::from_cpt(&cpt);
+        assert_eq!(da.base, expected.base);
+        assert_eq!(da.check, expected.check);
+        assert_eq!(da.codes, expected.codes);
+    }
+
+    #[test]
+    #[should_panic(expected = "not sorted")]
+    fn from_sorted_keys_rejects_unsorted_keys() {
+        DoubleArray::from_sorted_keys(vec![("い", 1), ("あ", 2)].into_iter());
+    }
+
+    #[test]
+    #[should_panic(expected = "not sorted")]
+    fn from_sorted_keys_rejects_duplicated_keys() {
+        DoubleArray::from_sorted_keys(vec![("あ", 1), ("あ", 2)].into_iter());
+    }
+
+    #[test]
+    #[should_panic(expected = "contains NUL")]
+    fn from_sorted_keys_rejects_nul() {
+        DoubleArray::from_sorted_keys(vec![("あ", 1), ("あ\0い", 2)].into_iter());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn from_sorted_keys_rejects_zero() {
+        DoubleArray::from_sorted_keys(vec![("あ", 0)].into_iter());
+    }
+
+    fn search_tree() -> DoubleArray {
+        let words = ["あ", "あい", "あいう", "あう", "い", "いう", "亜"];
+        DoubleArray::from_sorted_keys(words.iter().zip(1..).map(|(w, id)| (*w, id)))
+    }
+
+    #[test]
+    fn common_prefix_search() {
+        let da = search_tree();
+        assert_eq!(
+            da.common_prefix_search("あいうえ").collect::<Vec<_>>(),
+            vec![(1, 1), (2, 2), (3, 3)]
+        );
+        assert_eq!(
+            da.common_prefix_search("あえ").collect::<Vec<_>>(),
+            vec![(1, 1)]
+        );
+        assert_eq!(da.common_prefix_search("う").count(), 0);
+        assert_eq!(da.common_prefix_search("").count(), 0);
+    }
+
+    #[test]
+    fn predictive_search() {
+        let da = search_tree();
+        let search = |prefix| da.predictive_search(prefix).collect::<Vec<_>>();
+        assert_eq!(
+            search("あ"),
+            vec![
+                (String::from("あ"), 1),
+                (String::from("あい"), 2),
+                (String::from("あいう"), 3),
+                (String::from("あう"), 4)
+            ]
+        );
+        assert_eq!(search("あい").len(), 2);
+        assert_eq!(search("いう"), vec![(String::from("いう"), 6)]);
+        assert_eq!(search("う"), vec![]);
+        assert_eq!(search("あいうえ"), vec![]);
+        assert_eq!(
+            search("").into_iter().map(|(_, id)| id).collect::<Vec<_>>(),
+            (1..=7).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn children_in_order() {
+        let da = search_tree();
+        let (a, _) = da.init('あ').unwrap();
+        assert_eq!(
+            da.children(a as usize)
+                .into_iter()
+                .map(|(c, _)| c)
+                .collect::<Vec<_>>(),
+            vec!['い', 'う']
+        );
+        let (a, _) = da.init('亜').unwrap();
+        assert_eq!(da.children(a as usize), vec![]);
+    }
+
+    #[test]
+    fn search_archived_double_array() {
+        let da = search_tree();
+        let bytes = rkyv::to_bytes::<_, 256>(&da).unwrap();
+        let archived = unsafe { rkyv::archived_root::<DoubleArray>(&bytes) };
+        assert_eq!(
+            archived
+                .common_prefix_search("あいうえ")
+                .collect::<Vec<_>>(),
+            da.common_prefix_search("あいうえ").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            archived.predictive_search("あ").collect::<Vec<_>>(),
+            da.predictive_search("あ").collect::<Vec<_>>()
+        );
+    }
+}
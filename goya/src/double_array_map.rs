@@ -0,0 +1,243 @@
+//! A static map from strings to values, e.g. gazetteers and stopword lists.
+//!
+//! The double array maps a key to its number, which indexes the values, so the values aren't limited to
+//! the IDs in the double array and a key can have multiple values. It's always over the chars regardless of the
+//! `utf8-double-array` feature, so that an archived map can be shared by the builds with and without it.
+use super::double_array::{DoubleArray, Trie, INDEX_ROOT};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+#[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct DoubleArrayMap<V> {
+    da: DoubleArray,
+    /// index = number of the key - 1, value = end of the values of the key in `values`
+    ends: Vec<usize>,
+    values: Vec<V>,
+}
+impl<V> DoubleArrayMap<V> {
+    /// Builds the map from the pairs of a key and a value in any order.
+    /// The values of the same key are kept in the given order.
+    ///
+    /// # Panics
+    ///
+    /// A key must not contain NUL since it's the end of a key in the double array.
+    pub fn from_entries<K, I>(entries: I) -> Self
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut entries = entries.into_iter().collect::<Vec<_>>();
+        // Stable to keep the order of the values
+        entries.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        let mut keys: Vec<K> = vec![];
+        let mut ends = vec![];
+        let mut values = vec![];
+        for (key, value) in entries {
+            assert!(
+                !key.as_ref().contains('\0'),
+                "key {:?} contains NUL",
+                key.as_ref()
+            );
+            if keys.last().map(|last| last.as_ref()) != Some(key.as_ref()) {
+                if !keys.is_empty() {
+                    ends.push(values.len());
+                }
+                keys.push(key);
+            }
+            values.push(value);
+        }
+        if !keys.is_empty() {
+            ends.push(values.len());
+        }
+        assert!(
+            keys.len() <= i32::MAX as usize,
+            "too many keys: {}",
+            keys.len()
+        );
+        let da =
+            DoubleArray::from_sorted_keys(keys.iter().zip(1..).map(|(key, id)| (key.as_ref(), id)));
+        DoubleArrayMap { da, ends, values }
+    }
+
+    /// The number of the keys
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Returns the values of the key, or `None` when the key isn't found
+    pub fn get(&self, key: &str) -> Option<&[V]> {
+        values_of(&self.ends, &self.values, lookup(&self.da, key)?)
+    }
+
+    /// Returns `(length in chars, values)` of the keys which are prefixes of `text`, from the shortest one
+    pub fn common_prefix_search<'a>(
+        &'a self,
+        text: &'a str,
+    ) -> impl Iterator<Item = (usize, &'a [V])> + 'a {
+        self.da
+            .common_prefix_search(text)
+            .filter_map(move |(len, id)| Some((len, values_of(&self.ends, &self.values, id)?)))
+    }
+
+    /// Returns `(key, values)` of the keys which start with `prefix` in the order of the keys
+    pub fn predictive_search<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> impl Iterator<Item = (String, &'a [V])> + 'a {
+        self.da
+            .predictive_search(prefix)
+            .filter_map(move |(key, id)| Some((key, values_of(&self.ends, &self.values, id)?)))
+    }
+}
+impl<V: rkyv::Archive> ArchivedDoubleArrayMap<V> {
+    /// The number of the keys
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Same as `DoubleArrayMap::get`, but also `None` when `ends` is broken
+    pub fn get(&self, key: &str) -> Option<&[V::Archived]> {
+        values_of(&self.ends, &self.values, lookup(&self.da, key)?)
+    }
+
+    /// Same as `DoubleArrayMap::common_prefix_search`, skipping the keys whose `ends` are broken
+    pub fn common_prefix_search<'a>(
+        &'a self,
+        text: &'a str,
+    ) -> impl Iterator<Item = (usize, &'a [V::Archived])> + 'a {
+        self.da
+            .common_prefix_search(text)
+            .filter_map(move |(len, id)| Some((len, values_of(&self.ends, &self.values, id)?)))
+    }
+
+    /// Same as `DoubleArrayMap::predictive_search`, skipping the keys whose `ends` are broken
+    pub fn predictive_search<'a>(
+        &'a self,
+        prefix: &str,
+    ) -> impl Iterator<Item = (String, &'a [V::Archived])> + 'a {
+        self.da
+            .predictive_search(prefix)
+            .filter_map(move |(key, id)| Some((key, values_of(&self.ends, &self.values, id)?)))
+    }
+}
+
+fn lookup<T: Trie>(da: &T, key: &str) -> Option<usize> {
+    let mut cursor = INDEX_ROOT;
+    for c in key.chars() {
+        cursor = da.transition(cursor, c).ok()?.0 as usize;
+    }
+    da.stop(cursor).ok()
+}
+
+/// `id` is the number of the key. `ends` is either `Vec<usize>` or its archived form, which can be broken
+/// even if it passes the validation of rkyv, so it's `None` instead of a panic.
+fn values_of<'a, E, V>(ends: &[E], values: &'a [V], id: usize) -> Option<&'a [V]>
+where
+    E: Copy,
+    usize: TryFrom<E>,
+{
+    let end = usize::try_from(*ends.get(id.checked_sub(1)?)?).ok()?;
+    let start = match id {
+        1 => 0,
+        _ => usize::try_from(*ends.get(id - 2)?).ok()?,
+    };
+    values.get(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gazetteer() -> DoubleArrayMap<u64> {
+        DoubleArrayMap::from_entries(vec![
+            ("東京都", 13),
+            ("東京", 1),
+            ("京都", 26),
+            ("東京", u64::MAX),
+            ("京都府", 26),
+        ])
+    }
+
+    #[test]
+    fn get() {
+        let map = gazetteer();
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get("東京"), Some(&[1, u64::MAX][..]));
+        assert_eq!(map.get("京都"), Some(&[26][..]));
+        assert_eq!(map.get("東"), None);
+        assert_eq!(map.get("大阪"), None);
+        assert_eq!(map.get(""), None);
+    }
+
+    #[test]
+    fn search() {
+        let map = gazetteer();
+        assert_eq!(
+            map.common_prefix_search("東京都庁").collect::<Vec<_>>(),
+            vec![(2, &[1, u64::MAX][..]), (3, &[13][..])]
+        );
+        assert_eq!(
+            map.predictive_search("京").collect::<Vec<_>>(),
+            vec![
+                (String::from("京都"), &[26][..]),
+                (String::from("京都府"), &[26][..])
+            ]
+        );
+    }
+
+    #[test]
+    fn without_entries() {
+        let map = DoubleArrayMap::<u32>::from_entries(Vec::<(String, u32)>::new());
+        assert!(map.is_empty());
+        assert_eq!(map.get("東京"), None);
+        assert_eq!(map.predictive_search("").count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "contains NUL")]
+    fn reject_nul() {
+        DoubleArrayMap::from_entries(vec![("東京", 1), ("東\0京", 2)]);
+    }
+
+    #[test]
+    fn archived() {
+        let stopwords = DoubleArrayMap::from_entries(
+            ["の", "に", "は", "を", "に"]
+                .iter()
+                .zip(0u32..)
+                .map(|(w, id)| (w.to_string(), id)),
+        );
+        let bytes = rkyv::to_bytes::<_, 256>(&stopwords).unwrap();
+        let archived = rkyv::check_archived_root::<DoubleArrayMap<u32>>(&bytes).unwrap();
+        assert_eq!(archived.len(), 4);
+        assert_eq!(archived.get("に"), Some(&[1, 4][..]));
+        assert_eq!(archived.get("が"), None);
+        assert_eq!(
+            archived.common_prefix_search("のに").collect::<Vec<_>>(),
+            vec![(1, &[0][..])]
+        );
+        assert_eq!(
+            archived.predictive_search("").collect::<Vec<_>>(),
+            stopwords.predictive_search("").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn broken_ends() {
+        let values = [1u32, 2, 3];
+        assert_eq!(values_of(&[1u32, 3], &values, 2), Some(&[2, 3][..]));
+        assert_eq!(values_of(&[1u32, 4], &values, 2), None);
+        assert_eq!(values_of(&[2u32, 1], &values, 2), None);
+        assert_eq!(values_of(&[1u32], &values, 2), None);
+        assert_eq!(values_of(&[1u32], &values, 0), None);
+    }
+}
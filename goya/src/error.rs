@@ -0,0 +1,76 @@
+use super::id::WordIdentifier;
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors caused by an inconsistent dictionary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    KnownMorphemeNotFound(usize),
+    UnknownMorphemeNotFound(usize),
+    UserMorphemeNotFound(usize),
+    HomonymsNotFound(usize),
+    /// The class is not defined in char.def
+    CharClassNotFound(String),
+    /// No unknown word is defined for the class in unk.def
+    UnknownClassNotFound(String),
+    /// (left context ID, right context ID)
+    TransitionCostNotFound(usize, usize),
+    FeaturesNotFound(WordIdentifier),
+    /// The archive is broken or generated by another version of Goya
+    InvalidArchive(String),
+    /// The dictionary file is not a container compiled by Goya or it's broken
+    InvalidContainer(String),
+    /// (format version, version of Goya) of a container compiled by an incompatible version of Goya
+    IncompatibleFormat(u32, String),
+    /// (start, end) of a span constraint which can't be one token of the text
+    InvalidSpan(usize, usize),
+    /// Temperature of the marginal probabilities which isn't a finite positive number
+    InvalidTemperature(String),
+    /// No path reaches EOS in the lattice, which is a bug since every position has an unknown word at least
+    PathNotFound,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::KnownMorphemeNotFound(wid) => write!(f, "known morpheme {} is not found", wid),
+            Error::UnknownMorphemeNotFound(wid) => {
+                write!(f, "unknown morpheme {} is not found", wid)
+            }
+            Error::UserMorphemeNotFound(wid) => write!(f, "user morpheme {} is not found", wid),
+            Error::HomonymsNotFound(wid) => write!(f, "homonyms of {} are not found", wid),
+            Error::CharClassNotFound(class) => write!(f, "char class {} is not defined", class),
+            Error::UnknownClassNotFound(class) => {
+                write!(f, "no unknown word is defined for char class {}", class)
+            }
+            Error::TransitionCostNotFound(left, right) => {
+                write!(f, "transition cost from {} to {} is not found", left, right)
+            }
+            Error::FeaturesNotFound(wid) => write!(f, "features of {:?} are not found", wid),
+            Error::InvalidArchive(reason) => write!(f, "invalid archive of {}", reason),
+            Error::InvalidContainer(reason) => write!(f, "invalid dictionary file: {}", reason),
+            Error::IncompatibleFormat(format_version, goya_version) => write!(
+                f,
+                "the dictionary was compiled by Goya {} in format version {}, but Goya {} supports format version {}",
+                goya_version,
+                format_version,
+                env!("CARGO_PKG_VERSION"),
+                super::container::FORMAT_VERSION
+            ),
+            Error::InvalidSpan(start, end) => {
+                write!(
+                    f,
+                    "span {}..{} is empty, out of the text, starts with whitespace or crosses another constraint",
+                    start, end
+                )
+            }
+            Error::PathNotFound => write!(f, "no path is found in the lattice"),
+            Error::InvalidTemperature(temperature) => write!(
+                f,
+                "temperature {} is not a finite positive number",
+                temperature
+            ),
+        }
+    }
+}
+impl std::error::Error for Error {}
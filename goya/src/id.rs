@@ -1,16 +1,18 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "tag", content = "id")]
 pub enum WordIdentifier {
     Known(usize, String),   // ID, surface_form
     Unknown(usize, String), // ID, surface_form
+    User(usize, String),    // ID in the user dictionary, surface_form
 }
 impl WordIdentifier {
     pub fn get_surface(&self) -> &str {
         match self {
             Self::Known(_, surface) => surface,
             Self::Unknown(_, surface) => surface,
+            Self::User(_, surface) => surface,
         }
     }
 }
@@ -1,101 +1,130 @@
-use super::char_class::{CharDefinition, InvokeTiming};
+use super::char_class::{CharDefinitionRef, InvokeTiming, CLASS_SPACE};
+use super::constraint::Constraints;
 use super::dictionary::Dictionary;
-use super::double_array::DoubleArray;
+use super::double_array::Trie;
+use super::error::Result;
 use super::id::WordIdentifier;
-use std::collections::{HashSet, VecDeque};
+use super::marginal::Marginals;
+use super::morpheme::Morpheme;
+use super::offset::{OffsetTable, Offsets};
+use std::cmp::{self, Reverse};
+use std::collections::{BinaryHeap, HashSet, VecDeque};
 
 pub const BOS_CONTEXT_ID: usize = 0;
 pub const EOS_CONTEXT_ID: usize = 0;
 const NODE_BOS: usize = 0;
 
+/// List of `(index of dp, index of indices[i - 1])` from BOS to EOS
+pub type Path = Vec<(usize, usize)>;
+
 #[derive(Debug)]
 pub struct Lattice {
     // (wid, length of the word)
     pub indices: Vec<Vec<(WordIdentifier, usize)>>,
     // (min cost, index, length)
     pub dp: Vec<Vec<(i32, usize, usize)>>,
+    offset_table: OffsetTable,
+    text: String,
 }
 impl Lattice {
-    pub fn parse<D: Dictionary>(text: &str, da: &DoubleArray, dict: &D) -> Lattice {
-        let len = text.chars().count();
-        let mut indices: Vec<Vec<(WordIdentifier, usize)>> = vec![vec![]; len];
-        let mut open_indices = VecDeque::from(vec![0]);
-        let mut visited = HashSet::with_capacity(len);
+    pub fn parse<D: Dictionary, T: Trie>(text: &str, da: &T, dict: &D) -> Result<Lattice> {
+        Lattice::parse_with_constraints(text, da, dict, &Constraints::default())
+    }
+
+    /// Parses the text with the constraints like MeCab's partial analysis (`-p`).
+    /// Like MeCab, chars of the SPACE class are not tokenized. They are skipped as the leading whitespace of the next node.
+    pub fn parse_with_constraints<D: Dictionary, T: Trie>(
+        text: &str,
+        da: &T,
+        dict: &D,
+        constraints: &Constraints,
+    ) -> Result<Lattice> {
         let char_defs = text
             .chars()
             .map(|c| dict.classify_char(&c))
-            .collect::<Vec<&CharDefinition>>();
+            .collect::<Result<Vec<CharDefinitionRef>>>()?;
+        constraints.validate(char_defs.len(), |i| char_defs[i].class == CLASS_SPACE)?;
+        // Trailing whitespace is out of the lattice
+        let len = char_defs.len() - count_whitespace(char_defs.iter().rev());
+        let mut indices: Vec<Vec<(WordIdentifier, usize)>> = vec![vec![]; len];
+        let mut open_indices = VecDeque::from(vec![0]);
+        let mut visited = HashSet::with_capacity(len);
 
-        while let Some(index) = open_indices.pop_front() {
-            if visited.contains(&index) || index >= len {
+        while let Some(position) = open_indices.pop_front() {
+            if visited.contains(&position) || position >= len {
                 continue;
             }
-            visited.insert(index);
+            visited.insert(position);
+            let whitespace = count_whitespace(char_defs.iter().skip(position));
+            let index = position + whitespace;
 
-            let c = text.chars().nth(index).unwrap();
             let def = char_defs[index];
+            let limit = constraints.limit(index, len);
+            let mut candidates = vec![];
+
+            // User words precede system words to win ties
+            if let Some(user) = dict.user_dictionary() {
+                for (id, wlen) in prefix_matches(user.double_array(), text, index, len) {
+                    let surface: String = text.chars().skip(index).take(wlen).collect();
+                    for wid in user.resolve_homonyms(&id)?.iter() {
+                        candidates.push((WordIdentifier::User(*wid, surface.clone()), wlen));
+                    }
+                }
+            }
+            for (id, wlen) in prefix_matches(da, text, index, len) {
+                let surface: String = text.chars().skip(index).take(wlen).collect();
+                for wid in dict.resolve_homonyms(&id)?.iter() {
+                    candidates.push((WordIdentifier::Known(wid, surface.clone()), wlen));
+                }
+            }
+            // Unknown words follow known words to prefer known words on ties like MeCab
             if let InvokeTiming::Always = def.timing {
-                let surface_form = dict.take_unknown_chars_seq(def, text, &index);
-                open_indices.push_back(index + surface_form.chars().count());
-                for (wid, _) in dict.get_unknown_morphemes_by_class(&def.class) {
-                    indices[index].push((
-                        WordIdentifier::Unknown(wid, surface_form.to_string()),
-                        surface_form.chars().count(),
-                    ));
+                for wlen in dict.unknown_word_lengths(text, &index, &limit)? {
+                    push_unknown_words(&mut candidates, dict, &def, text, index, wlen)?;
                 }
             }
+            candidates.retain(|(wid, wlen)| constraints.accepts(index, *wlen, wid));
 
-            if let Ok((mut cursor, _)) = da.init(c) {
-                if let Ok(wid) = da.stop(cursor as usize) {
-                    open_indices.push_back(index + 1);
-                    for wid in dict.resolve_homonyms(&wid).unwrap().iter() {
-                        indices[index].push((
-                            WordIdentifier::Known(*wid, text.chars().skip(index).take(1).collect()),
-                            1,
-                        ));
+            match constraints.span_at(index) {
+                Some(span) if candidates.is_empty() => {
+                    // No word satisfies the span, so the whole span becomes an unknown word.
+                    // Trailing whitespace in the span is out of the lattice as well
+                    let wlen = cmp::min(span.end, len) - index;
+                    push_unknown_words(&mut candidates, dict, &def, text, index, wlen)?;
+                    // When no unknown word satisfies the filter either, the filter is ignored
+                    // so that the span is still one token, as documented in `Constraints::span_with`
+                    if candidates.iter().any(|(wid, _)| span.accepts(wid)) {
+                        candidates.retain(|(wid, _)| span.accepts(wid));
                     }
                 }
-                let mut j = index + 1;
-                while j < len {
-                    let c = text.chars().nth(j).unwrap();
-                    match da.transition(cursor as usize, c) {
-                        Ok((next, _)) => {
-                            if let Ok(wid) = da.stop(next as usize) {
-                                open_indices.push_back(j + 1);
-                                for wid in dict.resolve_homonyms(&wid).unwrap().iter() {
-                                    indices[index].push((
-                                        WordIdentifier::Known(
-                                            *wid,
-                                            text.chars().skip(index).take(j + 1 - index).collect(),
-                                        ),
-                                        j + 1 - index,
-                                    ));
-                                }
-                            }
-                            cursor = next;
-                        }
-                        Err(_) => {
-                            break;
+                None if candidates.is_empty() => {
+                    if let InvokeTiming::Fallback = def.timing {
+                        for wlen in dict.unknown_word_lengths(text, &index, &limit)? {
+                            push_unknown_words(&mut candidates, dict, &def, text, index, wlen)?;
                         }
                     }
-                    j += 1;
+                    if candidates.is_empty() {
+                        // e.g. the group is too long and the class has no length
+                        push_unknown_words(&mut candidates, dict, &def, text, index, 1)?;
+                    }
                 }
+                _ => {}
             }
-            if indices[index].is_empty() && matches!(def.timing, InvokeTiming::Fallback) {
-                let surface_form = dict.take_unknown_chars_seq(def, text, &index);
-                open_indices.push_back(index + surface_form.chars().count());
-                for (wid, _) in dict.get_unknown_morphemes_by_class(&def.class) {
-                    indices[index].push((
-                        WordIdentifier::Unknown(wid, surface_form.to_string()),
-                        surface_form.chars().count(),
-                    ));
-                }
+            for (_, wlen) in candidates.iter() {
+                open_indices.push_back(index + wlen);
             }
+            // Each node includes its leading whitespace
+            indices[position] = candidates
+                .into_iter()
+                .map(|(wid, wlen)| (wid, whitespace + wlen))
+                .collect();
         }
-        Lattice {
-            dp: get_dp_table(&indices, dict),
+        Ok(Lattice {
+            dp: get_dp_table(&indices, dict)?,
             indices,
-        }
+            offset_table: OffsetTable::new(text),
+            text: text.to_string(),
+        })
     }
 
     pub fn word_identifiers(&self) -> Vec<WordIdentifier> {
@@ -108,12 +137,14 @@ impl Lattice {
         wids
     }
 
+    /// Returns the best path, which is `None` only when no path reaches EOS. It doesn't happen since every position
+    /// has an unknown word at least, so the callers treat it as `Error::PathNotFound`
     pub fn find_best_path(&self) -> Option<Vec<(usize, usize)>> {
         let mut path = vec![];
         let mut cursor = (self.dp.len() - 1, 0);
         loop {
             match self.dp[cursor.0].get(cursor.1) {
-                Some((_, i, j)) => {
+                Some((cost, i, j)) if *cost != i32::MAX => {
                     if *i == NODE_BOS {
                         break;
                     }
@@ -138,14 +169,207 @@ impl Lattice {
             None => None,
         }
     }
+
+    pub fn find_best_with_offsets(&self) -> Option<Vec<(WordIdentifier, Offsets)>> {
+        self.find_best_path().map(|path| {
+            path.iter()
+                .map(|(i, j)| (self.indices[*i - 1][*j].0.clone(), self.offsets(*i, *j)))
+                .collect()
+        })
+    }
+
+    /// Returns the position of the node in the text excluding its leading whitespace.
+    /// `i` and `j` are the same as the elements of `Lattice::find_best_path`.
+    pub fn offsets(&self, i: usize, j: usize) -> Offsets {
+        let start = i - 1;
+        let (_, wlen) = self.indices[start][j];
+        self.offset_table
+            .get(start + self.whitespace_len(i, j), start + wlen)
+    }
+
+    /// Returns the whitespace skipped before the node
+    pub fn leading_whitespace(&self, i: usize, j: usize) -> &str {
+        let start = i - 1;
+        let bytes = self
+            .offset_table
+            .get(start, start + self.whitespace_len(i, j))
+            .bytes;
+        &self.text[bytes]
+    }
+
+    /// Returns the whitespace after the last node.
+    /// Concatenating the leading whitespace and the surface of the nodes in a path and this reproduces the text.
+    pub fn trailing_whitespace(&self) -> &str {
+        let len = self.indices.len();
+        &self.text[self.offset_table.get(len, len).bytes.start..]
+    }
+
+    fn whitespace_len(&self, i: usize, j: usize) -> usize {
+        let (wid, wlen) = &self.indices[i - 1][j];
+        wlen - wid.get_surface().chars().count()
+    }
+
+    /// Returns the top-k paths ordered by their total cost, like MeCab's `-N` option.
+    /// Each path is a list of `(index of dp, index of indices[i - 1])` and its cost.
+    pub fn nbest_paths<D: Dictionary>(&self, dict: &D, k: usize) -> Result<Vec<(Path, i32)>> {
+        let len = self.indices.len();
+        let mut paths = vec![];
+        if k == 0 {
+            return Ok(paths);
+        }
+        if len == 0 {
+            // Only BOS -> EOS
+            paths.push((vec![], self.dp[1][0].0));
+            return Ok(paths);
+        }
+        // ends[n] = nodes that end at the n-th char
+        let mut ends: Vec<Vec<(usize, usize)>> = vec![vec![]; len + 1];
+        for (i, index) in self.indices.iter().enumerate() {
+            for (j, (_, wlen)) in index.iter().enumerate() {
+                ends[cmp::min(i + wlen, len)].push((i + 1, j));
+            }
+        }
+
+        // Backward A* search. The forward costs in `dp` are exact, so paths are popped in order of their total cost.
+        // The partial paths share their tails in `arena` as (node, index of the next node toward EOS).
+        // (priority, index in arena for stable ordering, cost to EOS)
+        let mut arena: Vec<((usize, usize), Option<usize>)> = vec![];
+        let mut open = BinaryHeap::new();
+        for (i, j) in ends[len].iter() {
+            let before_cost = self.dp[*i][*j].0;
+            if before_cost == i32::MAX {
+                continue;
+            }
+            let left = dict.get(&self.indices[*i - 1][*j].0)?;
+            let cost = eos_cost(dict, &left)?;
+            open.push((Reverse(before_cost + cost), Reverse(arena.len()), cost));
+            arena.push(((*i, *j), None));
+        }
+        while let Some((Reverse(total), Reverse(node), cost)) = open.pop() {
+            let (i, j) = arena[node].0;
+            if i == NODE_BOS {
+                let mut path = vec![];
+                let mut next = arena[node].1;
+                while let Some(n) = next {
+                    path.push(arena[n].0);
+                    next = arena[n].1;
+                }
+                paths.push((path, total));
+                if paths.len() >= k {
+                    break;
+                }
+                continue;
+            }
+            let right = dict.get(&self.indices[i - 1][j].0)?;
+            if i == 1 {
+                open.push((Reverse(total), Reverse(arena.len()), cost));
+                arena.push(((NODE_BOS, 0), Some(node)));
+                continue;
+            }
+            for (pi, pj) in ends[i - 1].iter() {
+                let before_cost = self.dp[*pi][*pj].0;
+                if before_cost == i32::MAX {
+                    continue;
+                }
+                let left = dict.get(&self.indices[*pi - 1][*pj].0)?;
+                let cost = cost + connection_cost(dict, &left, &right)?;
+                open.push((Reverse(before_cost + cost), Reverse(arena.len()), cost));
+                arena.push(((*pi, *pj), Some(node)));
+            }
+        }
+        Ok(paths)
+    }
+
+    pub fn nbest<D: Dictionary>(
+        &self,
+        dict: &D,
+        k: usize,
+    ) -> Result<Vec<(Vec<WordIdentifier>, i32)>> {
+        Ok(self
+            .nbest_paths(dict, k)?
+            .into_iter()
+            .map(|(path, cost)| {
+                let ids = path
+                    .iter()
+                    .map(|(i, j)| self.indices[*i - 1][*j].0.clone())
+                    .collect();
+                (ids, cost)
+            })
+            .collect())
+    }
+
+    /// Computes the posterior probabilities of the nodes with the forward-backward algorithm.
+    /// A higher temperature gives a flatter distribution. It must be a finite positive number.
+    pub fn marginals<D: Dictionary>(&self, dict: &D, temperature: f64) -> Result<Marginals> {
+        Marginals::compute(self, dict, temperature)
+    }
+}
+
+/// Returns `(ID in the double array, length)` of the words which start at `start` and end until `end`
+fn prefix_matches<T: Trie>(da: &T, text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let rest = text
+        .char_indices()
+        .nth(start)
+        .map_or("", |(i, _)| &text[i..]);
+    da.common_prefix_search(rest)
+        .take_while(|(len, _)| *len <= end - start)
+        .map(|(len, id)| (id, len))
+        .collect()
+}
+
+fn count_whitespace<'a>(defs: impl Iterator<Item = &'a CharDefinitionRef<'a>>) -> usize {
+    defs.take_while(|def| def.class == CLASS_SPACE).count()
+}
+
+fn push_unknown_words<D: Dictionary>(
+    candidates: &mut Vec<(WordIdentifier, usize)>,
+    dict: &D,
+    def: &CharDefinitionRef,
+    text: &str,
+    start: usize,
+    wlen: usize,
+) -> Result<()> {
+    let surface_form: String = text.chars().skip(start).take(wlen).collect();
+    for (wid, _) in dict.get_unknown_morphemes_by_class(def.class)? {
+        candidates.push((WordIdentifier::Unknown(wid, surface_form.clone()), wlen));
+    }
+    Ok(())
+}
+
+/// Cost from BOS to the node including its word cost
+pub(crate) fn bos_cost<D: Dictionary>(dict: &D, right: &Morpheme) -> Result<i32> {
+    let cost = dict.transition_cost(&BOS_CONTEXT_ID, &right.left_context_id)?;
+    Ok(cost as i32 + right.cost as i32)
+}
+
+/// Cost from the node to EOS
+pub(crate) fn eos_cost<D: Dictionary>(dict: &D, left: &Morpheme) -> Result<i32> {
+    let cost = dict.transition_cost(&left.right_context_id, &EOS_CONTEXT_ID)?;
+    Ok(cost as i32)
+}
+
+/// Cost from the left node to the right node including the word cost of the right node.
+/// Like MeCab, it connects the right context ID of the left node and the left context ID of the right node.
+pub(crate) fn connection_cost<D: Dictionary>(
+    dict: &D,
+    left: &Morpheme,
+    right: &Morpheme,
+) -> Result<i32> {
+    let cost = dict.transition_cost(&left.right_context_id, &right.left_context_id)?;
+    Ok(cost as i32 + right.cost as i32)
+}
+
+/// MeCab prefers the left node that starts later on ties
+fn is_better(cost: i32, left: usize, current: &(i32, usize, usize)) -> bool {
+    cost < current.0 || (cost == current.0 && left > current.1)
 }
 
 fn get_dp_table<D: Dictionary>(
     indices: &[Vec<(WordIdentifier, usize)>],
     dict: &D,
-) -> Vec<Vec<(i32, usize, usize)>> {
+) -> Result<Vec<Vec<(i32, usize, usize)>>> {
     let len = indices.len();
-    let max_num_childs = indices.iter().map(|idx| idx.len()).max().unwrap();
+    let max_num_childs = indices.iter().map(|idx| idx.len()).max().unwrap_or(1);
     // (min cost, idx of indices, idx2 of indices[idx])
     // * dp[0][0] means BOS
     // * dp[dp.len() - 1][0] means EOS
@@ -153,49 +377,408 @@ fn get_dp_table<D: Dictionary>(
     // Currently each element has unused indices to reduce num alloc
     let mut dp: Vec<Vec<(i32, usize, usize)>> =
         vec![vec![(i32::MAX, 0, 0); max_num_childs]; len + 2];
-    if max_num_childs == 0 {
-        return dp;
-    }
     dp[0][0] = (0, 0, 0);
+    if len == 0 {
+        let cost = dict.transition_cost(&BOS_CONTEXT_ID, &EOS_CONTEXT_ID)?;
+        dp[1][0] = (cost as i32, NODE_BOS, 0);
+        return Ok(dp);
+    }
 
     for (i, (right_wid, _)) in indices[0].iter().enumerate() {
-        let right = dict.get(right_wid).unwrap();
-        let cost = dict
-            .transition_cost(&BOS_CONTEXT_ID, &right.right_context_id)
-            .unwrap()
-            + right.cost;
-        dp[1][i] = (cost as i32, NODE_BOS, 0);
+        let right = dict.get(right_wid)?;
+        dp[1][i] = (bos_cost(dict, &right)?, NODE_BOS, 0);
     }
 
     for (i, index) in indices.iter().enumerate() {
         for (j, (left_wid, wlen)) in index.iter().enumerate() {
             let before_cost = dp[i + 1][j].0;
-            let left = dict.get(left_wid).unwrap();
+            if before_cost == i32::MAX {
+                // Unreachable from BOS
+                continue;
+            }
+            let left = dict.get(left_wid)?;
             if i + wlen >= len {
-                let cost = (*dict
-                    .transition_cost(&left.left_context_id, &EOS_CONTEXT_ID)
-                    .unwrap() as i32)
-                    + (left.cost as i32)
-                    + before_cost;
-                if cost < dp[i + wlen + 1][0].0 {
+                let cost = eos_cost(dict, &left)? + before_cost;
+                if is_better(cost, i + 1, &dp[i + wlen + 1][0]) {
                     dp[i + wlen + 1][0] = (cost, i + 1, j);
                 }
                 continue;
             }
 
             for (k, (right_wid, _)) in indices[i + wlen].iter().enumerate() {
-                let right = dict.get(right_wid).unwrap();
-                let cost = (*dict
-                    .transition_cost(&left.left_context_id, &right.right_context_id)
-                    .unwrap() as i32)
-                    + left.cost as i32
-                    + right.cost as i32
-                    + before_cost;
-                if cost < dp[i + 1 + wlen][k].0 {
+                let right = dict.get(right_wid)?;
+                let cost = connection_cost(dict, &left, &right)? + before_cost;
+                if is_better(cost, i + 1, &dp[i + 1 + wlen][k]) {
                     dp[i + 1 + wlen][k] = (cost, i + 1, j);
                 }
             }
         }
     }
-    dp
+    Ok(dp)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::char_class::{CharClass, CharClassifier, CharDefinition, InvokeTiming};
+    use crate::common_prefix_tree::CommonPrefixTree;
+    use crate::dictionary::WordIds;
+    use crate::double_array::DoubleArray;
+    use crate::error::Error;
+    use crate::user_dictionary::{UserDictionary, WithUserDictionary};
+    use indexmap::{IndexMap, IndexSet};
+
+    pub(crate) struct TestDictionary {
+        known: Vec<Morpheme>,
+        homonyms: Vec<Vec<usize>>,
+        unknown: Vec<Morpheme>,
+        classes: CharClassifier,
+        matrix: Vec<Vec<i16>>,
+    }
+    impl Dictionary for TestDictionary {
+        fn get_known_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+            self.known
+                .get(*wid)
+                .copied()
+                .ok_or(Error::KnownMorphemeNotFound(*wid))
+        }
+        fn get_unknown_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+            self.unknown
+                .get(*wid)
+                .copied()
+                .ok_or(Error::UnknownMorphemeNotFound(*wid))
+        }
+        fn resolve_homonyms(&self, id: &usize) -> Result<WordIds<'_>> {
+            self.homonyms
+                .get(*id)
+                .map(|wids| WordIds::Owned(wids))
+                .ok_or(Error::HomonymsNotFound(*id))
+        }
+        fn unknown_word_lengths(
+            &self,
+            text: &str,
+            start: &usize,
+            end: &usize,
+        ) -> Result<Vec<usize>> {
+            self.classes.unknown_word_lengths(text, start, end)
+        }
+        fn classify_char(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+            self.classes.classify(c).map(CharDefinitionRef::from)
+        }
+        fn get_unknown_morphemes_by_class(&self, _class: &str) -> Result<Vec<(usize, Morpheme)>> {
+            Ok(self.unknown.iter().copied().enumerate().collect())
+        }
+        fn transition_cost(&self, left: &usize, right: &usize) -> Result<i16> {
+            self.matrix
+                .get(*left)
+                .and_then(|rights| rights.get(*right))
+                .copied()
+                .ok_or(Error::TransitionCostNotFound(*left, *right))
+        }
+        fn occurrence_cost(&self, wid: &usize) -> Result<i16> {
+            self.get_known_morpheme(wid).map(|m| m.cost)
+        }
+    }
+
+    /// Known words: あ(1), い(2), あい(3)
+    pub(crate) fn setup() -> (DoubleArray, TestDictionary) {
+        let mut cpt = CommonPrefixTree::default();
+        cpt.append(1, "あ");
+        cpt.append(2, "い");
+        cpt.append(3, "あい");
+        let mut chars = IndexMap::new();
+        for class in ["DEFAULT", "SPACE"] {
+            chars.insert(
+                String::from(class),
+                CharDefinition {
+                    class: String::from(class),
+                    timing: InvokeTiming::Fallback,
+                    group_by_same_kind: false,
+                    len: 1,
+                },
+            );
+        }
+        let spaces = vec![CharClass::from(
+            (0x20, 0x20),
+            String::from("SPACE"),
+            IndexSet::new(),
+        )];
+        let dict = TestDictionary {
+            known: vec![
+                Morpheme::new(0, 0, 0),
+                Morpheme::new(1, 1, 100),
+                Morpheme::new(1, 1, 200),
+                Morpheme::new(1, 1, 50),
+            ],
+            homonyms: vec![vec![], vec![1], vec![2], vec![3]],
+            unknown: vec![Morpheme::new(1, 1, 1000)],
+            classes: CharClassifier::from(chars, spaces),
+            matrix: vec![vec![0, 10], vec![20, 30]],
+        };
+        (DoubleArray::from_cpt(&cpt), dict)
+    }
+
+    fn surfaces(wids: &[WordIdentifier]) -> Vec<&str> {
+        wids.iter().map(|wid| wid.get_surface()).collect()
+    }
+
+    #[test]
+    fn nbest_orders_paths_by_cost() {
+        let (da, dict) = setup();
+        let lattice = Lattice::parse("あい", &da, &dict).unwrap();
+        let nbest = lattice.nbest(&dict, 10).unwrap();
+        assert_eq!(nbest.len(), 2);
+        assert_eq!(surfaces(&nbest[0].0), vec!["あい"]);
+        assert_eq!(surfaces(&nbest[1].0), vec!["あ", "い"]);
+        assert_eq!(nbest[0].1, 80);
+        assert_eq!(nbest[1].1, 360);
+        assert_eq!(nbest[0].1, lattice.dp[lattice.dp.len() - 1][0].0);
+    }
+
+    #[test]
+    fn nbest_first_path_equals_find_best() {
+        let (da, dict) = setup();
+        let lattice = Lattice::parse("あいあ", &da, &dict).unwrap();
+        let nbest = lattice.nbest(&dict, 1).unwrap();
+        assert_eq!(nbest.len(), 1);
+        assert_eq!(
+            surfaces(&nbest[0].0),
+            surfaces(&lattice.find_best().unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_with_boundary() {
+        let (da, dict) = setup();
+        let constraints = Constraints::default().boundary(1);
+        let lattice = Lattice::parse_with_constraints("あい", &da, &dict, &constraints).unwrap();
+        assert_eq!(surfaces(&lattice.find_best().unwrap()), vec!["あ", "い"]);
+    }
+
+    #[test]
+    fn parse_with_span() {
+        let (da, dict) = setup();
+        let constraints = Constraints::default().span(1, 3);
+        let lattice = Lattice::parse_with_constraints("あいあ", &da, &dict, &constraints).unwrap();
+        let best = lattice.find_best().unwrap();
+        assert_eq!(surfaces(&best), vec!["あ", "いあ"]);
+        assert!(matches!(best[1], WordIdentifier::Unknown(_, _)));
+    }
+
+    #[test]
+    fn parse_with_invalid_span() {
+        let (da, dict) = setup();
+        for (start, end) in [(2, 1), (1, 1), (1, 50)] {
+            let constraints = Constraints::default().span(start, end);
+            assert_eq!(
+                Lattice::parse_with_constraints("あいあいあ", &da, &dict, &constraints).err(),
+                Some(Error::InvalidSpan(start, end))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_with_span_from_whitespace() {
+        let (da, dict) = setup();
+        let constraints = Constraints::default().span(1, 3);
+        assert_eq!(
+            Lattice::parse_with_constraints("あ いあ", &da, &dict, &constraints).err(),
+            Some(Error::InvalidSpan(1, 3))
+        );
+    }
+
+    #[test]
+    fn parse_with_span_nothing_satisfies() {
+        let (da, dict) = setup();
+        let constraints = Constraints::default().span_with(0, 2, |_| false);
+        let lattice = Lattice::parse_with_constraints("あい", &da, &dict, &constraints).unwrap();
+        let best = lattice.find_best().unwrap();
+        assert_eq!(surfaces(&best), vec!["あい"]);
+        assert!(matches!(best[0], WordIdentifier::Unknown(_, _)));
+    }
+
+    #[test]
+    fn parse_with_span_over_trailing_whitespace() {
+        let (da, dict) = setup();
+        let constraints = Constraints::default().span(1, 3);
+        let lattice = Lattice::parse_with_constraints("あい ", &da, &dict, &constraints).unwrap();
+        assert_eq!(surfaces(&lattice.find_best().unwrap()), vec!["あ", "い"]);
+        assert_eq!(lattice.trailing_whitespace(), " ");
+    }
+
+    #[test]
+    fn parse_with_filtered_span() {
+        let (da, dict) = setup();
+        let constraints = Constraints::default()
+            .span_with(0, 2, |wid| matches!(wid, WordIdentifier::Known(3, _)));
+        let lattice = Lattice::parse_with_constraints("あい", &da, &dict, &constraints).unwrap();
+        assert_eq!(lattice.indices[0].len(), 1);
+        assert_eq!(surfaces(&lattice.find_best().unwrap()), vec!["あい"]);
+    }
+
+    #[test]
+    fn marginals_of_nodes_and_paths() {
+        let (da, dict) = setup();
+        let lattice = Lattice::parse("あい", &da, &dict).unwrap();
+        let marginals = lattice.marginals(&dict, 100.0).unwrap();
+        // あい: 80, あ+い: 360
+        let expected = 1.0 / (1.0 + (-2.8_f64).exp());
+        let best = lattice.find_best_path().unwrap();
+        assert!((marginals.node_probability(best[0].0, best[0].1) - expected).abs() < 1e-9);
+        assert!((marginals.path_probability(80) - expected).abs() < 1e-9);
+        assert!(
+            (marginals.path_probability(80) + marginals.path_probability(360) - 1.0).abs() < 1e-9
+        );
+        let total: f64 = lattice.indices[0]
+            .iter()
+            .enumerate()
+            .map(|(j, _)| marginals.node_probability(1, j))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn marginals_with_invalid_temperature() {
+        let (da, dict) = setup();
+        let lattice = Lattice::parse("あい", &da, &dict).unwrap();
+        for temperature in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                lattice.marginals(&dict, temperature).err(),
+                Some(Error::InvalidTemperature(temperature.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn find_best_with_offsets() {
+        let (da, dict) = setup();
+        let lattice = Lattice::parse("あいあ", &da, &dict).unwrap();
+        let best = lattice.find_best_with_offsets().unwrap();
+        assert_eq!(
+            best.iter().map(|(_, o)| o.clone()).collect::<Vec<_>>(),
+            vec![
+                Offsets {
+                    chars: 0..2,
+                    bytes: 0..6,
+                    utf16: 0..2
+                },
+                Offsets {
+                    chars: 2..3,
+                    bytes: 6..9,
+                    utf16: 2..3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_skips_whitespace() {
+        let (da, dict) = setup();
+        let text = " あ  い ";
+        let lattice = Lattice::parse(text, &da, &dict).unwrap();
+        let path = lattice.find_best_path().unwrap();
+        assert_eq!(surfaces(&lattice.find_best().unwrap()), vec!["あ", "い"]);
+        assert_eq!(
+            path.iter()
+                .map(|(i, j)| lattice.leading_whitespace(*i, *j))
+                .collect::<Vec<_>>(),
+            vec![" ", "  "]
+        );
+        assert_eq!(lattice.offsets(path[1].0, path[1].1).chars, 4..5);
+        assert_eq!(lattice.trailing_whitespace(), " ");
+
+        let concatenated = path
+            .iter()
+            .map(|(i, j)| {
+                let (wid, _) = &lattice.indices[*i - 1][*j];
+                format!(
+                    "{}{}",
+                    lattice.leading_whitespace(*i, *j),
+                    wid.get_surface()
+                )
+            })
+            .collect::<String>();
+        assert_eq!(concatenated + lattice.trailing_whitespace(), text);
+    }
+
+    #[test]
+    fn parse_empty_text() {
+        let (da, dict) = setup();
+        for text in ["", "   "] {
+            let lattice = Lattice::parse(text, &da, &dict).unwrap();
+            assert!(lattice.indices.is_empty());
+            assert_eq!(lattice.find_best_path(), Some(vec![]));
+            assert_eq!(lattice.nbest_paths(&dict, 2).unwrap(), vec![(vec![], 0)]);
+            assert_eq!(lattice.trailing_whitespace(), text);
+        }
+    }
+
+    #[test]
+    fn parse_any_unicode() {
+        let (da, dict) = setup();
+        for text in [
+            "\u{0301}",
+            "あ\u{0301}い",
+            "👨\u{200D}👩\u{200D}👧",
+            "\u{0}あ\u{0}",
+            "\u{7}\u{1B}[0m\r\n",
+            "\u{FEFF}\u{FFFF}\u{10FFFF}",
+        ] {
+            let lattice = Lattice::parse(text, &da, &dict).unwrap();
+            let path = lattice.find_best_path().unwrap();
+            let concatenated = path
+                .iter()
+                .map(|(i, j)| {
+                    let (wid, _) = &lattice.indices[*i - 1][*j];
+                    format!(
+                        "{}{}",
+                        lattice.leading_whitespace(*i, *j),
+                        wid.get_surface()
+                    )
+                })
+                .collect::<String>();
+            assert_eq!(concatenated + lattice.trailing_whitespace(), text);
+        }
+    }
+
+    #[test]
+    fn parse_with_broken_dictionary() {
+        let (da, mut dict) = setup();
+        dict.matrix = vec![vec![0]];
+        assert_eq!(
+            Lattice::parse("あ", &da, &dict).unwrap_err(),
+            Error::TransitionCostNotFound(0, 1)
+        );
+        dict.homonyms.pop();
+        assert_eq!(
+            Lattice::parse("あい", &da, &dict).unwrap_err(),
+            Error::HomonymsNotFound(3)
+        );
+    }
+
+    #[test]
+    fn parse_with_user_dictionary() {
+        let (da, system) = setup();
+        let user = UserDictionary::from(vec![
+            (String::from("あい"), Morpheme::new(1, 1, 50), vec![]),
+            (String::from("うえ"), Morpheme::new(1, 1, 10), vec![]),
+        ]);
+        let dict = WithUserDictionary {
+            system: &system,
+            user: Some(&user),
+        };
+        let lattice = Lattice::parse("あいうえ", &da, &dict).unwrap();
+        // The user word wins the tie with the system word
+        assert_eq!(
+            lattice.find_best().unwrap(),
+            vec![
+                WordIdentifier::User(0, String::from("あい")),
+                WordIdentifier::User(1, String::from("うえ"))
+            ]
+        );
+        assert_eq!(
+            lattice.nbest(&dict, 2).unwrap()[1].1,
+            lattice.nbest(&dict, 1).unwrap()[0].1
+        );
+    }
 }
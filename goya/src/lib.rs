@@ -1,9 +1,21 @@
 pub mod char_class;
 pub mod common_prefix_tree;
+pub mod constraint;
+pub mod container;
 pub mod dictionary;
 pub mod dot;
 pub mod double_array;
+pub mod double_array_map;
+pub mod error;
 pub mod id;
 pub mod lattice;
+pub mod marginal;
 pub mod morpheme;
+pub mod offset;
+pub mod tokenizer;
+pub mod user_dictionary;
+pub mod utf8_double_array;
 pub mod word_features;
+
+pub use error::{Error, Result};
+pub use tokenizer::{Token, Tokenizer};
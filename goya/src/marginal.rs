@@ -0,0 +1,143 @@
+use super::dictionary::Dictionary;
+use super::error::{Error, Result};
+use super::lattice::{bos_cost, connection_cost, eos_cost, Lattice};
+
+/// Posterior probabilities of the lattice nodes computed by the forward-backward algorithm,
+/// like MeCab's marginal probability mode (`-m`, `-a`).
+/// The probability of a path is proportional to `exp(-cost / temperature)`.
+#[derive(Debug)]
+pub struct Marginals {
+    temperature: f64,
+    /// log of the partition function
+    log_z: f64,
+    /// index = the same as `Lattice::indices`
+    probabilities: Vec<Vec<f64>>,
+}
+impl Marginals {
+    pub fn compute<D: Dictionary>(
+        lattice: &Lattice,
+        dict: &D,
+        temperature: f64,
+    ) -> Result<Marginals> {
+        if !temperature.is_finite() || temperature <= 0.0 {
+            return Err(Error::InvalidTemperature(temperature.to_string()));
+        }
+        let len = lattice.indices.len();
+        let weight = |cost: i32| -(cost as f64) / temperature;
+        let mut alpha: Vec<Vec<f64>> = lattice
+            .indices
+            .iter()
+            .map(|index| vec![f64::NEG_INFINITY; index.len()])
+            .collect();
+        let mut beta = alpha.clone();
+        // ends[n] = nodes that end at the n-th char
+        let mut ends: Vec<Vec<(usize, usize)>> = vec![vec![]; len + 1];
+        for (i, index) in lattice.indices.iter().enumerate() {
+            for (j, (_, wlen)) in index.iter().enumerate() {
+                ends[(i + wlen).min(len)].push((i, j));
+            }
+        }
+
+        for (i, index) in lattice.indices.iter().enumerate() {
+            for (j, (wid, _)) in index.iter().enumerate() {
+                let right = dict.get(wid)?;
+                alpha[i][j] = if i == 0 {
+                    weight(bos_cost(dict, &right)?)
+                } else {
+                    let values = ends[i]
+                        .iter()
+                        .map(|(pi, pj)| {
+                            let left = dict.get(&lattice.indices[*pi][*pj].0)?;
+                            Ok(alpha[*pi][*pj] + weight(connection_cost(dict, &left, &right)?))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    log_sum_exp(values.into_iter())
+                };
+            }
+        }
+        for (i, index) in lattice.indices.iter().enumerate().rev() {
+            for (j, (wid, wlen)) in index.iter().enumerate() {
+                let left = dict.get(wid)?;
+                beta[i][j] = if i + wlen >= len {
+                    weight(eos_cost(dict, &left)?)
+                } else {
+                    let values = lattice.indices[i + wlen]
+                        .iter()
+                        .enumerate()
+                        .map(|(k, (right_wid, _))| {
+                            let right = dict.get(right_wid)?;
+                            Ok(beta[i + wlen][k] + weight(connection_cost(dict, &left, &right)?))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    log_sum_exp(values.into_iter())
+                };
+            }
+        }
+
+        let log_z = if len == 0 {
+            // Only BOS -> EOS
+            weight(lattice.dp[1][0].0)
+        } else {
+            log_sum_exp(ends[len].iter().map(|(i, j)| alpha[*i][*j] + beta[*i][*j]))
+        };
+        let probabilities = alpha
+            .iter()
+            .zip(beta.iter())
+            .map(|(a, b)| {
+                a.iter()
+                    .zip(b.iter())
+                    .map(|(a, b)| (a + b - log_z).exp())
+                    .collect()
+            })
+            .collect();
+        Ok(Marginals {
+            temperature,
+            log_z,
+            probabilities,
+        })
+    }
+
+    /// Returns the posterior probability of the node.
+    /// `i` and `j` are the same as the elements of `Lattice::find_best_path`.
+    pub fn node_probability(&self, i: usize, j: usize) -> f64 {
+        i.checked_sub(1)
+            .and_then(|i| self.probabilities.get(i))
+            .and_then(|index| index.get(j))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Returns the normalized probability of a path which has the total cost.
+    pub fn path_probability(&self, cost: i32) -> f64 {
+        (-(cost as f64) / self.temperature - self.log_z).exp()
+    }
+
+    pub fn log_z(&self) -> f64 {
+        self.log_z
+    }
+}
+
+fn log_sum_exp(values: impl Iterator<Item = f64>) -> f64 {
+    let values: Vec<f64> = values.collect();
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_sum_exp_of_values() {
+        let actual = log_sum_exp(vec![1.0_f64.ln(), 2.0_f64.ln(), 3.0_f64.ln()].into_iter());
+        assert!((actual - 6.0_f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn log_sum_exp_of_nothing() {
+        assert_eq!(log_sum_exp(vec![].into_iter()), f64::NEG_INFINITY);
+    }
+}
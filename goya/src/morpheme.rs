@@ -1,18 +1,21 @@
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
 
 #[derive(
     Debug,
-    Hash,
     PartialEq,
     Eq,
     PartialOrd,
     Clone,
+    Copy,
     Serialize,
     Deserialize,
     rkyv::Archive,
     rkyv::Serialize,
     rkyv::Deserialize,
 )]
+#[archive(check_bytes)]
+#[archive_attr(derive(PartialEq, Eq))]
 pub struct Morpheme {
     /// 左文脈ID (単語を左から見たときの文脈 ID)
     /// https://taku910.github.io/mecab/dic-detail.html
@@ -33,3 +36,19 @@ impl Morpheme {
         }
     }
 }
+// The archived one must have the same hash to validate the archived `IndexSet<Morpheme>`.
+// IDs are hashed as u64 so that the hash doesn't depend on the platform.
+impl Hash for Morpheme {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.left_context_id as u64).hash(state);
+        (self.right_context_id as u64).hash(state);
+        self.cost.hash(state);
+    }
+}
+impl Hash for ArchivedMorpheme {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.left_context_id as u64).hash(state);
+        (self.right_context_id as u64).hash(state);
+        self.cost.hash(state);
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Position of a token in the original text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Offsets {
+    /// in chars (Unicode scalar values)
+    pub chars: Range<usize>,
+    /// in UTF-8 bytes
+    pub bytes: Range<usize>,
+    /// in UTF-16 code units. e.g. `String.prototype.slice` in JavaScript
+    pub utf16: Range<usize>,
+}
+
+/// Converts char offsets into other units
+#[derive(Debug, Default)]
+pub struct OffsetTable {
+    // index = char offset, value = offset of the char in each unit
+    bytes: Vec<usize>,
+    utf16: Vec<usize>,
+}
+impl OffsetTable {
+    pub fn new(text: &str) -> OffsetTable {
+        let mut bytes = vec![0];
+        let mut utf16 = vec![0];
+        for c in text.chars() {
+            bytes.push(bytes[bytes.len() - 1] + c.len_utf8());
+            utf16.push(utf16[utf16.len() - 1] + c.len_utf16());
+        }
+        OffsetTable { bytes, utf16 }
+    }
+
+    pub fn get(&self, start: usize, end: usize) -> Offsets {
+        Offsets {
+            chars: start..end,
+            bytes: self.bytes[start]..self.bytes[end],
+            utf16: self.utf16[start]..self.utf16[end],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_in_each_unit() {
+        let table = OffsetTable::new("aあ𠮷b");
+        assert_eq!(
+            table.get(0, 1),
+            Offsets {
+                chars: 0..1,
+                bytes: 0..1,
+                utf16: 0..1
+            }
+        );
+        assert_eq!(
+            table.get(1, 3),
+            Offsets {
+                chars: 1..3,
+                bytes: 1..8,
+                utf16: 1..4
+            }
+        );
+        assert_eq!(
+            table.get(3, 4),
+            Offsets {
+                chars: 3..4,
+                bytes: 8..9,
+                utf16: 4..5
+            }
+        );
+    }
+}
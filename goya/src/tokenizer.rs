@@ -0,0 +1,336 @@
+use super::dictionary::Dictionary;
+use super::double_array::{SystemDoubleArray, Trie};
+use super::error::{Error, Result};
+use super::id::WordIdentifier;
+use super::lattice::{bos_cost, connection_cost, Lattice};
+use super::offset::Offsets;
+use super::user_dictionary::{UserDictionary, WithUserDictionary};
+use super::word_features::WordFeaturesMap;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{
+    archived_root, check_archived_root, Archive, Archived, CheckBytes, Deserialize, Infallible,
+};
+use serde::Serialize;
+use std::fmt;
+
+/// A morpheme in the analyzed text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Token {
+    pub wid: WordIdentifier,
+    pub surface: String,
+    /// Whitespace skipped before the token
+    pub leading_whitespace: String,
+    pub offsets: Offsets,
+    /// Empty when the tokenizer has no word features
+    pub features: Vec<String>,
+    pub is_known: bool,
+    pub left_context_id: usize,
+    pub right_context_id: usize,
+    pub word_cost: i16,
+    /// Cost from BOS to the token including its word cost, like MeCab's `%pC`
+    pub cumulative_cost: i32,
+}
+
+/// Owns the dictionary and tokenizes texts with the best path.
+/// It's `Send + Sync` when the dictionary is, so it can be shared across threads.
+/// The double array and the dictionary can be references to archives, e.g. the sections of a memory-mapped `dict.goya`,
+/// to skip the deserialization.
+pub struct Tokenizer<D, T = SystemDoubleArray> {
+    da: T,
+    dict: D,
+    features: Option<WordFeaturesMap>,
+    user: Option<UserDictionary>,
+}
+impl<D: Dictionary, T: Trie> Tokenizer<D, T> {
+    pub fn from(da: T, dict: D) -> Tokenizer<D, T> {
+        Tokenizer {
+            da,
+            dict,
+            features: None,
+            user: None,
+        }
+    }
+
+    /// Fills `Token::features` with the word features
+    pub fn with_features(mut self, features: WordFeaturesMap) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    /// Looks up the words of the user dictionary before the system dictionary
+    pub fn with_user_dictionary(mut self, user: UserDictionary) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Replaces or removes the user dictionary
+    pub fn set_user_dictionary(&mut self, user: Option<UserDictionary>) {
+        self.user = user;
+    }
+
+    pub fn double_array(&self) -> &T {
+        &self.da
+    }
+
+    /// The system dictionary with the user dictionary, which the lattices need
+    pub fn dictionary(&self) -> WithUserDictionary<'_, D> {
+        WithUserDictionary {
+            system: &self.dict,
+            user: self.user.as_ref(),
+        }
+    }
+
+    pub fn system_dictionary(&self) -> &D {
+        &self.dict
+    }
+
+    pub fn user_dictionary(&self) -> Option<&UserDictionary> {
+        self.user.as_ref()
+    }
+
+    pub fn word_features(&self) -> Option<&WordFeaturesMap> {
+        self.features.as_ref()
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Lattice> {
+        Lattice::parse(text, &self.da, &self.dictionary())
+    }
+
+    pub fn tokenize(&self, text: &str) -> Result<Vec<Token>> {
+        let lattice = self.parse(text)?;
+        let path = lattice.find_best_path().ok_or(Error::PathNotFound)?;
+        self.tokens(&lattice, &path)
+    }
+
+    /// Converts a path of the lattice, e.g. one of `Lattice::nbest_paths`, into tokens
+    pub fn tokens(&self, lattice: &Lattice, path: &[(usize, usize)]) -> Result<Vec<Token>> {
+        let dict = self.dictionary();
+        let mut tokens: Vec<Token> = Vec::with_capacity(path.len());
+        for (i, j) in path.iter() {
+            let wid = &lattice.indices[*i - 1][*j].0;
+            let morpheme = dict.get(wid)?;
+            let cumulative_cost = match tokens.last() {
+                Some(left) => {
+                    let left = dict.get(&left.wid)?;
+                    connection_cost(&dict, &left, &morpheme)?
+                }
+                None => bos_cost(&dict, &morpheme)?,
+            } + tokens.last().map_or(0, |left| left.cumulative_cost);
+            let features = match (wid, &self.features, &self.user) {
+                (WordIdentifier::User(id, _), _, Some(user)) => user.get_features(id)?.clone(),
+                (_, Some(features), _) => features
+                    .get(wid)
+                    .ok_or_else(|| Error::FeaturesNotFound(wid.clone()))?
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+                _ => vec![],
+            };
+            tokens.push(Token {
+                wid: wid.clone(),
+                surface: wid.get_surface().to_string(),
+                leading_whitespace: lattice.leading_whitespace(*i, *j).to_string(),
+                offsets: lattice.offsets(*i, *j),
+                features,
+                is_known: !matches!(wid, WordIdentifier::Unknown(_, _)),
+                left_context_id: morpheme.left_context_id,
+                right_context_id: morpheme.right_context_id,
+                word_cost: morpheme.cost,
+                cumulative_cost,
+            });
+        }
+        Ok(tokens)
+    }
+}
+impl<D: Dictionary> Tokenizer<D> {
+    /// Validates and deserializes the double array and the dictionary archived by `goya compile`, i.e. the sections of `dict.goya`.
+    /// Broken bytes or ones generated by another version of Goya result in `Error::InvalidArchive`.
+    pub fn from_archived<'b>(da: &'b [u8], dict: &'b [u8]) -> Result<Tokenizer<D>>
+    where
+        D: Archive,
+        D::Archived: 'b + CheckBytes<DefaultValidator<'b>> + Deserialize<D, Infallible>,
+        <D::Archived as CheckBytes<DefaultValidator<'b>>>::Error: fmt::Display,
+    {
+        let da = check_archive::<SystemDoubleArray>(da, "double array")?
+            .deserialize(&mut Infallible)
+            .unwrap();
+        let dict = check_archive::<D>(dict, "dictionary")?
+            .deserialize(&mut Infallible)
+            .unwrap();
+        Ok(Tokenizer::from(da, dict))
+    }
+
+    /// Same as `Tokenizer::from_archived` without the validation, which is faster for trusted bytes, e.g. embedded ones.
+    ///
+    /// # Safety
+    ///
+    /// The bytes are not validated, so they must be generated by `goya compile` with the same version of Goya.
+    pub unsafe fn from_archived_unchecked(da: &[u8], dict: &[u8]) -> Tokenizer<D>
+    where
+        D: Archive,
+        D::Archived: Deserialize<D, Infallible>,
+    {
+        let da = archived_root::<SystemDoubleArray>(da)
+            .deserialize(&mut Infallible)
+            .unwrap();
+        let dict = archived_root::<D>(dict)
+            .deserialize(&mut Infallible)
+            .unwrap();
+        Tokenizer::from(da, dict)
+    }
+}
+impl<'a, D: Dictionary> Tokenizer<&'a D, &'a Archived<SystemDoubleArray>> {
+    /// Works directly on the archives generated by `goya compile` without deserialization.
+    /// Memory-mapped files start up instantly and share their pages across processes.
+    /// The bytes are validated like `Tokenizer::from_archived`.
+    pub fn from_archived_ref<R>(da: &'a [u8], dict: &'a [u8]) -> Result<Self>
+    where
+        R: Archive<Archived = D>,
+        D: CheckBytes<DefaultValidator<'a>>,
+        <D as CheckBytes<DefaultValidator<'a>>>::Error: fmt::Display,
+    {
+        Ok(Tokenizer::from(
+            check_archive::<SystemDoubleArray>(da, "double array")?,
+            check_archive::<R>(dict, "dictionary")?,
+        ))
+    }
+
+    /// Same as `Tokenizer::from_archived_ref` without the validation.
+    ///
+    /// # Safety
+    ///
+    /// The bytes are not validated, so they must be generated by `goya compile` with the same version of Goya.
+    pub unsafe fn from_archived_ref_unchecked<R>(da: &'a [u8], dict: &'a [u8]) -> Self
+    where
+        R: Archive<Archived = D>,
+    {
+        Tokenizer::from(
+            archived_root::<SystemDoubleArray>(da),
+            archived_root::<R>(dict),
+        )
+    }
+}
+
+fn check_archive<'a, T: Archive>(bytes: &'a [u8], name: &str) -> Result<&'a T::Archived>
+where
+    T::Archived: CheckBytes<DefaultValidator<'a>>,
+    <T::Archived as CheckBytes<DefaultValidator<'a>>>::Error: fmt::Display,
+{
+    check_archived_root::<T>(bytes)
+        .map_err(|err| Error::InvalidArchive(format!("{}: {}", name, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::double_array::DoubleArray;
+    use crate::lattice::tests::{setup, TestDictionary};
+    use crate::morpheme::Morpheme;
+    use rkyv::ser::{serializers::AllocSerializer, Serializer};
+    use rkyv::AlignedVec;
+
+    fn tokenizer() -> Tokenizer<TestDictionary, DoubleArray> {
+        let (da, dict) = setup();
+        let features = WordFeaturesMap::new(
+            vec![
+                vec![],
+                vec![String::from("A")],
+                vec![String::from("I")],
+                vec![String::from("AI")],
+            ],
+            vec![vec![String::from("UNK")]],
+        );
+        Tokenizer::from(da, dict).with_features(features)
+    }
+
+    #[test]
+    fn tokenizer_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Tokenizer<TestDictionary>>();
+    }
+
+    #[test]
+    fn tokenize() {
+        let tokens = tokenizer().tokenize("あいあ う").unwrap();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|t| (
+                    t.surface.as_str(),
+                    t.leading_whitespace.as_str(),
+                    t.features.join(","),
+                    t.is_known
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("あい", "", String::from("AI"), true),
+                ("あ", "", String::from("A"), true),
+                ("う", " ", String::from("UNK"), false),
+            ]
+        );
+        assert_eq!(tokens[2].offsets.chars, 4..5);
+        assert_eq!(
+            tokens.iter().map(|t| t.cumulative_cost).collect::<Vec<_>>(),
+            vec![60, 190, 1220]
+        );
+    }
+
+    #[test]
+    fn tokenize_empty_text() {
+        assert_eq!(tokenizer().tokenize("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn tokenize_with_user_dictionary() {
+        let mut tokenizer = tokenizer().with_user_dictionary(UserDictionary::from(vec![(
+            String::from("あい"),
+            Morpheme::new(1, 1, 50),
+            vec![String::from("USER")],
+        )]));
+        let tokens = tokenizer.tokenize("あい").unwrap();
+        assert_eq!(tokens[0].wid, WordIdentifier::User(0, String::from("あい")));
+        assert_eq!(tokens[0].features, vec![String::from("USER")]);
+        assert!(tokens[0].is_known);
+
+        tokenizer.set_user_dictionary(None);
+        let tokens = tokenizer.tokenize("あい").unwrap();
+        assert_eq!(tokens[0].features, vec![String::from("AI")]);
+    }
+
+    #[test]
+    fn tokenize_with_archived_double_array() {
+        let (da, dict) = setup();
+        let mut serializer = AllocSerializer::<256>::default();
+        serializer.serialize_value(&da).unwrap();
+        let bytes = serializer.into_serializer().into_inner();
+        let archived = Tokenizer::from(unsafe { archived_root::<DoubleArray>(&bytes) }, &dict);
+        let owned = Tokenizer::from(da, &dict);
+        for text in ["あいあ う", "いあい", ""] {
+            assert_eq!(
+                archived.tokenize(text).unwrap(),
+                owned.tokenize(text).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn check_archive_rejects_broken_bytes() {
+        let (da, _) = setup();
+        let mut serializer = AllocSerializer::<256>::default();
+        serializer.serialize_value(&da).unwrap();
+        let bytes = serializer.into_serializer().into_inner();
+        let archived = check_archive::<DoubleArray>(&bytes, "da.bin").unwrap();
+        assert_eq!(archived.init('あ').ok(), da.init('あ').ok());
+
+        let mut broken = AlignedVec::new();
+        broken.extend_from_slice(&bytes[..bytes.len() - 4]);
+        assert!(matches!(
+            check_archive::<DoubleArray>(&broken, "da.bin"),
+            Err(Error::InvalidArchive(_))
+        ));
+        assert!(matches!(
+            check_archive::<DoubleArray>(&[], "da.bin"),
+            Err(Error::InvalidArchive(_))
+        ));
+    }
+}
@@ -0,0 +1,189 @@
+use super::char_class::CharDefinitionRef;
+use super::dictionary::{Dictionary, WordIds};
+use super::double_array::DoubleArray;
+use super::error::{Error, Result};
+use super::morpheme::Morpheme;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+/// Words added on top of the system dictionary without recompiling it, like MeCab's user dictionary.
+/// The context IDs must be the ones of the system dictionary.
+#[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct UserDictionary {
+    da: DoubleArray,
+    /// index = ID in the double array - 1, elements = word IDs which have the same surface form
+    homonyms: Vec<Vec<usize>>,
+    morphemes: Vec<Morpheme>,   // index = word ID
+    features: Vec<Vec<String>>, // index = word ID
+}
+impl UserDictionary {
+    /// Builds the dictionary from `(surface form, morpheme, features)`
+    pub fn from(words: Vec<(String, Morpheme, Vec<String>)>) -> UserDictionary {
+        let mut surfaces: IndexMap<String, Vec<usize>> = IndexMap::new();
+        let mut morphemes = vec![];
+        let mut features = vec![];
+        for (wid, (surface, morpheme, f)) in words.into_iter().enumerate() {
+            surfaces.entry(surface).or_default().push(wid);
+            morphemes.push(morpheme);
+            features.push(f);
+        }
+        let mut keys = surfaces
+            .keys()
+            .zip(1..)
+            .map(|(surface, id)| (surface.as_str(), id))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        UserDictionary {
+            da: DoubleArray::from_sorted_keys(keys.into_iter()),
+            homonyms: surfaces.into_iter().map(|(_, wids)| wids).collect(),
+            morphemes,
+            features,
+        }
+    }
+
+    pub fn double_array(&self) -> &DoubleArray {
+        &self.da
+    }
+
+    pub fn get(&self, wid: &usize) -> Result<&Morpheme> {
+        self.morphemes
+            .get(*wid)
+            .ok_or(Error::UserMorphemeNotFound(*wid))
+    }
+
+    pub fn get_features(&self, wid: &usize) -> Result<&Vec<String>> {
+        self.features
+            .get(*wid)
+            .ok_or(Error::UserMorphemeNotFound(*wid))
+    }
+
+    /// `id` is a value of the double array
+    pub fn resolve_homonyms(&self, id: &usize) -> Result<&Vec<usize>> {
+        id.checked_sub(1)
+            .and_then(|idx| self.homonyms.get(idx))
+            .ok_or(Error::HomonymsNotFound(*id))
+    }
+
+    /// Returns `(surface form, word ID)` of the words in the order of the surface forms
+    pub fn words(&self) -> Vec<(String, usize)> {
+        let mut words = vec![];
+        for (surface, id) in self.da.entries() {
+            for wid in self.homonyms[id - 1].iter() {
+                words.push((surface.clone(), *wid));
+            }
+        }
+        words
+    }
+
+    pub fn len(&self) -> usize {
+        self.morphemes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.morphemes.is_empty()
+    }
+}
+
+/// The system dictionary with an optional user dictionary.
+/// `Lattice::parse` looks up words in both of them.
+pub struct WithUserDictionary<'a, D> {
+    pub system: &'a D,
+    pub user: Option<&'a UserDictionary>,
+}
+impl<'a, D: Dictionary> Dictionary for WithUserDictionary<'a, D> {
+    fn get_known_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        self.system.get_known_morpheme(wid)
+    }
+    fn get_unknown_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        self.system.get_unknown_morpheme(wid)
+    }
+    fn resolve_homonyms(&self, id: &usize) -> Result<WordIds<'_>> {
+        self.system.resolve_homonyms(id)
+    }
+    fn unknown_word_lengths(&self, text: &str, start: &usize, end: &usize) -> Result<Vec<usize>> {
+        self.system.unknown_word_lengths(text, start, end)
+    }
+    fn classify_char(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+        self.system.classify_char(c)
+    }
+    fn get_unknown_morphemes_by_class(&self, class: &str) -> Result<Vec<(usize, Morpheme)>> {
+        self.system.get_unknown_morphemes_by_class(class)
+    }
+    fn transition_cost(&self, left: &usize, right: &usize) -> Result<i16> {
+        self.system.transition_cost(left, right)
+    }
+    fn occurrence_cost(&self, wid: &usize) -> Result<i16> {
+        self.system.occurrence_cost(wid)
+    }
+    fn user_dictionary(&self) -> Option<&UserDictionary> {
+        self.user
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::double_array::Trie;
+
+    #[test]
+    fn from_words() {
+        let dict = UserDictionary::from(vec![
+            (
+                String::from("ゴヤ"),
+                Morpheme::new(1, 1, 10),
+                vec![String::from("A")],
+            ),
+            (
+                String::from("ゴーヤ"),
+                Morpheme::new(1, 1, 20),
+                vec![String::from("B")],
+            ),
+            (
+                String::from("ゴヤ"),
+                Morpheme::new(2, 2, 30),
+                vec![String::from("C")],
+            ),
+        ]);
+        assert_eq!(dict.len(), 3);
+        let (cursor, _) = dict.double_array().init('ゴ').unwrap();
+        let (cursor, _) = dict
+            .double_array()
+            .transition(cursor as usize, 'ヤ')
+            .unwrap();
+        let id = dict.double_array().stop(cursor as usize).unwrap();
+        assert_eq!(dict.resolve_homonyms(&id).unwrap(), &vec![0, 2]);
+        assert_eq!(dict.get(&2).unwrap(), &Morpheme::new(2, 2, 30));
+        assert_eq!(dict.get_features(&1).unwrap(), &vec![String::from("B")]);
+        assert_eq!(dict.get(&3), Err(Error::UserMorphemeNotFound(3)));
+        assert_eq!(
+            dict.words(),
+            vec![
+                (String::from("ゴヤ"), 0),
+                (String::from("ゴヤ"), 2),
+                (String::from("ゴーヤ"), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn from_few_words() {
+        // Chars of a few words are likely to have larger codes than the free indices of the double array
+        let dict = UserDictionary::from(vec![(
+            String::from("コンピューター"),
+            Morpheme::new(1, 1, 10),
+            vec![],
+        )]);
+        let da = dict.double_array();
+        let mut chars = "コンピューター".chars();
+        let (mut cursor, _) = da.init(chars.next().unwrap()).unwrap();
+        for c in chars {
+            cursor = da.transition(cursor as usize, c).unwrap().0;
+        }
+        assert_eq!(
+            dict.resolve_homonyms(&da.stop(cursor as usize).unwrap())
+                .unwrap(),
+            &vec![0]
+        );
+    }
+}
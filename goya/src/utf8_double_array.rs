@@ -0,0 +1,286 @@
+//! A double array over the UTF-8 bytes of the keys, which is experimental.
+//!
+//! The code of a byte is the byte + 1 and 0 is the end of a key, so a transition is a direct access to the arrays
+//! without looking up the code of a char. A char takes 1 to 4 transitions instead, so the arrays are larger than
+//! the ones of `DoubleArray`. The common prefix search is faster on synthetic words, but a faster analysis hasn't
+//! been measured.
+use super::double_array::{Builder, Keys, TransitionError, Trie, INDEX_ROOT};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::str;
+
+#[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct Utf8DoubleArray {
+    pub base: Vec<i32>,
+    pub check: Vec<usize>,
+}
+impl Default for Utf8DoubleArray {
+    fn default() -> Self {
+        Utf8DoubleArray {
+            base: vec![0, 1],
+            check: vec![0, 0],
+        }
+    }
+}
+impl Utf8DoubleArray {
+    /// Same as `DoubleArray::from_sorted_keys`
+    ///
+    /// # Panics
+    ///
+    /// The keys must be sorted and unique, and the IDs must be in `1..=i32::MAX` since a negative base means an ID.
+    pub fn from_sorted_keys<'a, I>(keys: I) -> Self
+    where
+        I: Iterator<Item = (&'a str, u32)>,
+    {
+        let keys = Keys::from_sorted(keys, str::bytes);
+        let (base, check) = Builder::build(&keys, |b| *b as usize + 1);
+        Utf8DoubleArray { base, check }
+    }
+
+    /// Returns all the words and their IDs in the order of the words
+    pub fn entries(&self) -> Vec<(String, usize)> {
+        let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, parent) in self.check.iter().enumerate() {
+            if *parent != 0 {
+                children.entry(*parent).or_default().push(index);
+            }
+        }
+        let mut entries = vec![];
+        let mut open = vec![(INDEX_ROOT, vec![])];
+        while let Some((parent, prefix)) = open.pop() {
+            for index in children.get(&parent).into_iter().flatten() {
+                match *index as i32 - self.base[parent] {
+                    0 if self.base[*index] < 0 => entries.push((
+                        String::from_utf8(prefix.clone()).unwrap(),
+                        -self.base[*index] as usize,
+                    )),
+                    0 => {}
+                    code => {
+                        let mut key = prefix.clone();
+                        key.push((code - 1) as u8);
+                        open.push((*index, key));
+                    }
+                }
+            }
+        }
+        entries.sort();
+        entries
+    }
+}
+
+/// Cells of the double array, which the archived one shares
+trait Cells {
+    fn get_base(&self, index: usize) -> Option<i32>;
+    fn get_check(&self, index: usize) -> Option<usize>;
+}
+impl Cells for Utf8DoubleArray {
+    fn get_base(&self, index: usize) -> Option<i32> {
+        self.base.get(index).copied()
+    }
+
+    fn get_check(&self, index: usize) -> Option<usize> {
+        self.check.get(index).copied()
+    }
+}
+impl Cells for ArchivedUtf8DoubleArray {
+    fn get_base(&self, index: usize) -> Option<i32> {
+        self.base.get(index).copied()
+    }
+
+    fn get_check(&self, index: usize) -> Option<usize> {
+        self.check.get(index).map(|check| *check as usize)
+    }
+}
+
+impl Trie for Utf8DoubleArray {
+    fn transition(&self, from: usize, to: char) -> Result<(i32, Option<usize>), TransitionError> {
+        transition(self, from, to)
+    }
+
+    fn stop(&self, from: usize) -> Result<usize, TransitionError> {
+        stop(self, from)
+    }
+
+    fn children(&self, index: usize) -> Vec<(char, usize)> {
+        children(self, index)
+    }
+}
+impl Trie for ArchivedUtf8DoubleArray {
+    fn transition(&self, from: usize, to: char) -> Result<(i32, Option<usize>), TransitionError> {
+        transition(self, from, to)
+    }
+
+    fn stop(&self, from: usize) -> Result<usize, TransitionError> {
+        stop(self, from)
+    }
+
+    fn children(&self, index: usize) -> Vec<(char, usize)> {
+        children(self, index)
+    }
+}
+
+fn next<T: Cells>(da: &T, from: usize, code: usize) -> Result<usize, TransitionError> {
+    let base = da.get_base(from).ok_or(TransitionError::BaseOutOfBounds)?;
+    if base < 0 {
+        return Err(TransitionError::AlreadyTerminated);
+    }
+    let t = base as usize + code;
+    match da.get_check(t) {
+        Some(check) if check == from => Ok(t),
+        Some(_) => Err(TransitionError::CheckFailed),
+        None => Err(TransitionError::CheckOutOfBounds),
+    }
+}
+
+fn transition<T: Cells>(
+    da: &T,
+    from: usize,
+    to: char,
+) -> Result<(i32, Option<usize>), TransitionError> {
+    let mut index = from;
+    for b in to.encode_utf8(&mut [0; 4]).bytes() {
+        index = next(da, index, b as usize + 1)?;
+    }
+    let base = da.get_base(index).ok_or(TransitionError::BaseFailed)?;
+    let wid = if base < 0 { Some(-base as usize) } else { None };
+    Ok((index as i32, wid))
+}
+
+fn stop<T: Cells>(da: &T, from: usize) -> Result<usize, TransitionError> {
+    match da.get_base(next(da, from, 0)?) {
+        Some(base) if base < 0 => Ok(-base as usize),
+        _ => Err(TransitionError::BaseFailed),
+    }
+}
+
+fn children<T: Cells>(da: &T, index: usize) -> Vec<(char, usize)> {
+    let mut children = vec![];
+    collect_children(da, index, &mut Vec::with_capacity(4), &mut children);
+    children
+}
+
+/// Follows the bytes until they make a char. The order of the bytes is the order of the chars
+fn collect_children<T: Cells>(
+    da: &T,
+    index: usize,
+    bytes: &mut Vec<u8>,
+    children: &mut Vec<(char, usize)>,
+) {
+    let base = match da.get_base(index) {
+        Some(base) if base >= 0 => base as usize,
+        _ => return,
+    };
+    // Only the bytes which can follow in UTF-8 are looked up, i.e. the leading bytes at the start of a char
+    // and the continuation bytes after them
+    let candidates: &[RangeInclusive<u8>] = if bytes.is_empty() {
+        &[0x00..=0x7F, 0xC2..=0xF4]
+    } else {
+        &[0x80..=0xBF]
+    };
+    for b in candidates.iter().cloned().flatten() {
+        let t = base + b as usize + 1;
+        match da.get_check(t) {
+            Some(check) if check == index => {}
+            Some(_) => continue,
+            // The indices after it are out of the check as well
+            None => return,
+        }
+        bytes.push(b);
+        match str::from_utf8(bytes) {
+            Ok(s) => children.push((s.chars().next().unwrap(), t)),
+            // Incomplete char
+            Err(err) if err.error_len().is_none() => collect_children(da, t, bytes, children),
+            Err(_) => {}
+        }
+        bytes.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::double_array::DoubleArray;
+
+    const WORDS: [&str; 9] = [
+        "a",
+        "ab",
+        "あ",
+        "あい",
+        "あいう",
+        "あう",
+        "い",
+        "亜",
+        "𠮷野",
+    ];
+
+    fn build(words: &[&str]) -> (Utf8DoubleArray, DoubleArray) {
+        let keys = || words.iter().zip(1..).map(|(w, id)| (*w, id));
+        (
+            Utf8DoubleArray::from_sorted_keys(keys()),
+            DoubleArray::from_sorted_keys(keys()),
+        )
+    }
+
+    #[test]
+    fn same_as_double_array() {
+        let (utf8, da) = build(&WORDS);
+        assert_eq!(utf8.entries(), da.entries());
+        for text in ["あいうえお", "abc", "亜い", "いい", "う", ""] {
+            assert_eq!(
+                utf8.common_prefix_search(text).collect::<Vec<_>>(),
+                da.common_prefix_search(text).collect::<Vec<_>>(),
+                "{}",
+                text
+            );
+        }
+        for prefix in ["", "あ", "a", "亜", "う", "𠮷"] {
+            assert_eq!(
+                utf8.predictive_search(prefix).collect::<Vec<_>>(),
+                da.predictive_search(prefix).collect::<Vec<_>>(),
+                "{}",
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    fn transition_by_chars() {
+        let (da, _) = build(&WORDS);
+        let (cursor, wid) = da.init('あ').unwrap();
+        assert_eq!(wid, None);
+        assert_eq!(da.stop(cursor as usize).ok(), Some(3));
+        let (cursor, _) = da.transition(cursor as usize, 'い').unwrap();
+        assert_eq!(da.stop(cursor as usize).ok(), Some(4));
+        assert!(da.transition(cursor as usize, 'え').is_err());
+        // A char which shares the leading bytes with the keys
+        assert!(da.init('ぃ').is_err());
+        assert!(da.init('\0').is_err());
+    }
+
+    #[test]
+    fn without_words() {
+        let (da, _) = build(&[]);
+        assert_eq!(da.entries(), vec![]);
+        assert!(da.init('あ').is_err());
+        assert_eq!(da.predictive_search("").count(), 0);
+    }
+
+    #[test]
+    fn archived() {
+        let (da, _) = build(&WORDS);
+        let bytes = rkyv::to_bytes::<_, 256>(&da).unwrap();
+        let archived = rkyv::check_archived_root::<Utf8DoubleArray>(&bytes).unwrap();
+        assert_eq!(
+            archived
+                .common_prefix_search("あいうえ")
+                .collect::<Vec<_>>(),
+            da.common_prefix_search("あいうえ").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            archived.predictive_search("").collect::<Vec<_>>(),
+            da.predictive_search("").collect::<Vec<_>>()
+        );
+    }
+}
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use std::str::from_utf8_unchecked;
 
 #[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
 pub struct WordFeaturesMap {
     #[serde(with = "serde_bytes")]
     index: Vec<u8>,
@@ -21,11 +22,11 @@ impl WordFeaturesMap {
         }
         let mut index = vec![];
         let mut offsets: Vec<usize> = vec![0; tmp_index.len()];
-        offsets[0] = tmp_index.get_index(0).unwrap().as_bytes().len();
+        offsets[0] = tmp_index.get_index(0).unwrap().len();
         for (idx, str) in tmp_index.iter().enumerate() {
             index.append(&mut str.to_string().into_bytes());
             if idx > 0 {
-                offsets[idx] = offsets[idx - 1] + str.as_bytes().len();
+                offsets[idx] = offsets[idx - 1] + str.len();
             }
         }
 
@@ -51,6 +52,8 @@ impl WordFeaturesMap {
         match wid {
             WordIdentifier::Known(wid, _) => self.get_known(wid),
             WordIdentifier::Unknown(wid, _) => self.get_unknown(wid),
+            // Features of user words are in the user dictionary
+            WordIdentifier::User(_, _) => None,
         }
     }
 
@@ -62,6 +65,21 @@ impl WordFeaturesMap {
         self.unknown.get(*wid).map(|f| self.get_string(f))
     }
 
+    /// Returns true if the features start with the pattern. `*` in the pattern matches any feature.
+    /// e.g. `["名詞", "固有名詞"]` matches `名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー`
+    pub fn matches(&self, wid: &WordIdentifier, pattern: &[&str]) -> bool {
+        match self.get(wid) {
+            Some(features) => {
+                features.len() >= pattern.len()
+                    && pattern
+                        .iter()
+                        .zip(features.iter())
+                        .all(|(p, f)| *p == "*" || p == f)
+            }
+            None => false,
+        }
+    }
+
     fn get_string(&self, f: &WordFeatures) -> Vec<&str> {
         f.0.iter()
             .map(|idx| {
@@ -80,9 +98,33 @@ impl WordFeaturesMap {
 /// > 5カラム目以降は, ユーザ定義の CSV フィールドです. 基本的に どんな内容でも CSV の許す限り追加することができます.
 /// > https://taku910.github.io/mecab/dic-detail.html
 #[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
 pub struct WordFeatures(Vec<usize>);
 impl WordFeatures {
     pub fn new(features: Vec<usize>) -> WordFeatures {
         WordFeatures(features)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_prefix_of_features() {
+        let map = WordFeaturesMap::new(
+            vec![vec![
+                String::from("名詞"),
+                String::from("固有名詞"),
+                String::from("地域"),
+            ]],
+            vec![],
+        );
+        let wid = WordIdentifier::Known(0, String::from("東京"));
+        assert!(map.matches(&wid, &["名詞", "固有名詞"]));
+        assert!(map.matches(&wid, &["名詞", "*", "地域"]));
+        assert!(!map.matches(&wid, &["名詞", "一般"]));
+        assert!(!map.matches(&wid, &["名詞", "固有名詞", "地域", "一般"]));
+        assert!(!map.matches(&WordIdentifier::Unknown(0, String::new()), &["名詞"]));
+    }
+}
@@ -0,0 +1,92 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use goya::dictionary::Dictionary;
+use goya::double_array::{DoubleArray, Trie};
+use goya::utf8_double_array::Utf8DoubleArray;
+use goya::Tokenizer;
+use goya_ipadic::ipadic_loader::{IPADicLoader, LoadResult};
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+const FIXTURES: &str = "tests/fixtures";
+
+/// The fixture dictionary, or IPADIC at `GOYA_BENCH_IPADIC` if it's set
+fn load() -> (String, LoadResult) {
+    let dir = env::var("GOYA_BENCH_IPADIC").unwrap_or_else(|_| format!("{}/mini-ipadic", FIXTURES));
+    let start = Instant::now();
+    let loaded = IPADicLoader {}.load(&dir).unwrap();
+    eprintln!("loaded {} in {:?}", dir, start.elapsed());
+    (dir, loaded)
+}
+
+fn sorted_keys(loaded: &LoadResult) -> Vec<(&str, u32)> {
+    let mut keys = loaded
+        .surfaces
+        .iter()
+        .map(|(id, surface)| (surface.as_str(), *id as u32))
+        .collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys
+}
+
+fn archived_size<T: rkyv::Serialize<rkyv::ser::serializers::AllocSerializer<256>>>(
+    value: &T,
+) -> usize {
+    rkyv::to_bytes::<_, 256>(value).unwrap().len()
+}
+
+fn tokenize_all<D: Dictionary, T: Trie>(tokenizer: &Tokenizer<D, T>, text: &[String]) -> usize {
+    text.iter()
+        .map(|line| tokenizer.tokenize(line).unwrap().len())
+        .sum()
+}
+
+fn bench(c: &mut Criterion) {
+    let (dir, loaded) = load();
+    let keys = sorted_keys(&loaded);
+
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    group.bench_with_input(BenchmarkId::new("DoubleArray", &dir), &keys, |b, keys| {
+        b.iter(|| DoubleArray::from_sorted_keys(keys.iter().copied()))
+    });
+    group.bench_with_input(
+        BenchmarkId::new("Utf8DoubleArray", &dir),
+        &keys,
+        |b, keys| b.iter(|| Utf8DoubleArray::from_sorted_keys(keys.iter().copied())),
+    );
+    group.finish();
+
+    let da = DoubleArray::from_sorted_keys(keys.iter().copied());
+    let utf8 = Utf8DoubleArray::from_sorted_keys(keys.iter().copied());
+    eprintln!(
+        "da.bin of {} words: DoubleArray {} bytes, Utf8DoubleArray {} bytes",
+        keys.len(),
+        archived_size(&da),
+        archived_size(&utf8)
+    );
+    // Lattice::parse and the best path of each line
+    let corpus = fs::read_to_string(format!("{}/corpus.txt", FIXTURES)).unwrap();
+    let text = corpus
+        .lines()
+        .cycle()
+        .take(1000)
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let da = Tokenizer::from(da, &loaded.ipadic);
+    let utf8 = Tokenizer::from(utf8, &loaded.ipadic);
+    let mut group = c.benchmark_group("tokenize");
+    group.throughput(Throughput::Bytes(
+        text.iter().map(|line| line.len() as u64).sum(),
+    ));
+    group.bench_function(BenchmarkId::new("DoubleArray", &dir), |b| {
+        b.iter(|| tokenize_all(&da, &text))
+    });
+    group.bench_function(BenchmarkId::new("Utf8DoubleArray", &dir), |b| {
+        b.iter(|| tokenize_all(&utf8, &text))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use super::ipadic::IPADic;
+use goya::dictionary::Dictionary;
+use goya::double_array::SystemDoubleArray;
+use goya::id::WordIdentifier;
+use goya::morpheme::Morpheme;
+use goya::user_dictionary::UserDictionary;
+use goya::word_features::WordFeaturesMap;
+use goya::{Error, Result};
+
+/// (surface form, morpheme, features)
+pub type Word<'a> = (String, Morpheme, Vec<&'a str>);
+
+/// Returns the known words reachable from the double array in the order of the surface forms
+pub fn known_words<'a>(
+    da: &SystemDoubleArray,
+    dict: &'a IPADic,
+    features: &'a WordFeaturesMap,
+) -> Result<Vec<Word<'a>>> {
+    let mut words = vec![];
+    for (surface, id) in da.entries() {
+        for wid in dict.resolve_homonyms(&id)?.iter() {
+            let f = features.get_known(&wid).ok_or_else(|| {
+                Error::FeaturesNotFound(WordIdentifier::Known(wid, surface.to_string()))
+            })?;
+            words.push((surface.to_string(), dict.get_known_morpheme(&wid)?, f));
+        }
+    }
+    Ok(words)
+}
+
+/// Returns the words of the user dictionary in the order of the surface forms
+pub fn user_words(user: &UserDictionary) -> Result<Vec<Word<'_>>> {
+    let mut words = vec![];
+    for (surface, wid) in user.words() {
+        let f = user
+            .get_features(&wid)?
+            .iter()
+            .map(String::as_str)
+            .collect();
+        words.push((surface, *user.get(&wid)?, f));
+    }
+    Ok(words)
+}
+
+/// Returns the unknown words in the order of the char classes. Their surface forms are the classes
+pub fn unknown_words<'a>(dict: &'a IPADic, features: &'a WordFeaturesMap) -> Result<Vec<Word<'a>>> {
+    let mut classes = dict.unknown_classes().iter().collect::<Vec<_>>();
+    classes.sort();
+    let mut words = vec![];
+    for (class, wids) in classes {
+        for wid in wids.iter() {
+            let f = features.get_unknown(wid).ok_or_else(|| {
+                Error::FeaturesNotFound(WordIdentifier::Unknown(*wid, class.to_string()))
+            })?;
+            words.push((class.to_string(), dict.get_unknown_morpheme(wid)?, f));
+        }
+    }
+    Ok(words)
+}
+
+/// Formats the words like the CSVs of IPADIC
+pub fn to_csv(words: &[Word]) -> String {
+    let mut csv = String::new();
+    for (surface, morpheme, features) in words.iter() {
+        let ids = [
+            morpheme.left_context_id.to_string(),
+            morpheme.right_context_id.to_string(),
+            morpheme.cost.to_string(),
+        ];
+        let mut columns = vec![surface.as_str()];
+        columns.extend(ids.iter().map(|id| id.as_str()));
+        columns.extend(features.iter());
+        csv.push_str(&join_csv(&columns));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Formats the connection costs like matrix.def
+pub fn to_matrix_def(dict: &IPADic) -> String {
+    let matrix = dict.matrix();
+    let rsize = matrix.first().map_or(0, |row| row.len());
+    let mut def = format!("{} {}\n", matrix.len(), rsize);
+    for (left, row) in matrix.iter().enumerate() {
+        for (right, cost) in row.iter().enumerate() {
+            def.push_str(&format!("{} {} {}\n", left, right, cost));
+        }
+    }
+    def
+}
+
+/// Joins the columns as a CSV row. Columns which have commas or double quotes are quoted
+pub(crate) fn join_csv(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .map(|c| {
+            if c.contains(',') || c.contains('"') {
+                format!("\"{}\"", c.replace('"', "\"\""))
+            } else {
+                c.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_csv_quotes_columns() {
+        let morpheme = Morpheme::new(1, 2, -3);
+        assert_eq!(
+            to_csv(&[(
+                String::from("も"),
+                morpheme,
+                vec!["助詞", "動詞%F2@-1,名詞%F1", "\"A\""]
+            )]),
+            "も,1,2,-3,助詞,\"動詞%F2@-1,名詞%F1\",\"\"\"A\"\"\"\n"
+        );
+    }
+}
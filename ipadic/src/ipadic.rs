@@ -1,84 +1,219 @@
 use goya::char_class::CharClassifier;
-use goya::char_class::CharDefinition;
-use goya::dictionary::Dictionary;
-use goya::morpheme::Morpheme;
-use indexmap::IndexSet;
+use goya::char_class::CharDefinitionRef;
+use goya::dictionary::{Dictionary, WordIds};
+use goya::morpheme::{ArchivedMorpheme, Morpheme};
+use goya::{Error, Result};
+use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::iter::FromIterator;
 use std::vec::Vec;
 
 // TODO: Make it newtype idiom
 type MorphemeIndex = usize;
 
 #[derive(Debug, Serialize, Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
 pub struct IPADic {
     vocabulary: Vec<MorphemeIndex>, // index = morpheme ID
-    homonyms: Vec<Vec<usize>>,      // index = morpheme ID
+    /// index = ID in the double array - 1, elements = morpheme IDs which have the same surface form
+    homonyms: Vec<Vec<usize>>,
     classes: CharClassifier,
     matrix: Vec<Vec<i16>>,
     /// 1つのカテゴリに複数の素性を定義してもかまいません. 学習後, 適切なコスト値が 自動的に与えられます.
     /// https://taku910.github.io/mecab/learn.html#config
-    unknown_classes: HashMap<String, Vec<usize>>,
+    unknown_classes: IndexMap<String, Vec<usize>>,
     unknown_vocabulary: Vec<MorphemeIndex>, // index = morpheme ID
     vocabulary_index: IndexSet<Morpheme>,
 }
 impl Dictionary for IPADic {
-    fn get_known_morpheme(&self, wid: &usize) -> Option<&Morpheme> {
-        self.vocabulary
-            .get(*wid)
-            .map(|idx| self.vocabulary_index.get_index(*idx).unwrap())
+    fn get_known_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        get_known_morpheme(self, wid)
     }
 
-    fn get_unknown_morpheme(&self, wid: &usize) -> Option<&Morpheme> {
-        self.unknown_vocabulary
-            .get(*wid)
-            .map(|idx| self.vocabulary_index.get_index(*idx).unwrap())
+    fn get_unknown_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        get_unknown_morpheme(self, wid)
     }
 
-    fn resolve_homonyms(&self, wid: &usize) -> Option<&Vec<usize>> {
-        self.homonyms.get(*wid)
+    fn resolve_homonyms(&self, id: &usize) -> Result<WordIds<'_>> {
+        resolve_homonyms(self, id)
     }
 
-    fn take_unknown_chars_seq(&self, def: &CharDefinition, text: &str, start: &usize) -> String {
-        self.classes.take_unknown_chars(def, text, start)
+    fn unknown_word_lengths(&self, text: &str, start: &usize, end: &usize) -> Result<Vec<usize>> {
+        self.classes.unknown_word_lengths(text, start, end)
     }
 
-    fn classify_char(&self, c: &char) -> &CharDefinition {
+    fn classify_char(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
+        self.classes.classify(c).map(CharDefinitionRef::from)
+    }
+
+    fn get_unknown_morphemes_by_class(&self, class: &str) -> Result<Vec<(usize, Morpheme)>> {
+        get_unknown_morphemes_by_class(self, class)
+    }
+
+    fn transition_cost(&self, left: &usize, right: &usize) -> Result<i16> {
+        transition_cost(self, left, right)
+    }
+
+    fn occurrence_cost(&self, wid: &usize) -> Result<i16> {
+        self.get_known_morpheme(wid).map(|w| w.cost)
+    }
+}
+/// Looks up the archive directly, e.g. a section of a memory-mapped `dict.goya`, without deserialization
+impl Dictionary for ArchivedIPADic {
+    fn get_known_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        get_known_morpheme(self, wid)
+    }
+
+    fn get_unknown_morpheme(&self, wid: &usize) -> Result<Morpheme> {
+        get_unknown_morpheme(self, wid)
+    }
+
+    fn resolve_homonyms(&self, id: &usize) -> Result<WordIds<'_>> {
+        resolve_homonyms(self, id)
+    }
+
+    fn unknown_word_lengths(&self, text: &str, start: &usize, end: &usize) -> Result<Vec<usize>> {
+        self.classes.unknown_word_lengths(text, start, end)
+    }
+
+    fn classify_char(&self, c: &char) -> Result<CharDefinitionRef<'_>> {
         self.classes.classify(c)
     }
 
-    fn get_unknown_morphemes_by_class(&self, class: &str) -> Vec<(usize, &Morpheme)> {
+    fn get_unknown_morphemes_by_class(&self, class: &str) -> Result<Vec<(usize, Morpheme)>> {
+        get_unknown_morphemes_by_class(self, class)
+    }
+
+    fn transition_cost(&self, left: &usize, right: &usize) -> Result<i16> {
+        transition_cost(self, left, right)
+    }
+
+    fn occurrence_cost(&self, wid: &usize) -> Result<i16> {
+        self.get_known_morpheme(wid).map(|w| w.cost)
+    }
+}
+
+/// Fields of the dictionary, which the archived one shares
+trait Tables {
+    /// Index of the morpheme in `vocabulary_index` of the known word
+    fn get_vocabulary(&self, wid: usize) -> Option<usize>;
+    /// Index of the morpheme in `vocabulary_index` of the unknown word
+    fn get_unknown_vocabulary(&self, wid: usize) -> Option<usize>;
+    fn get_morpheme(&self, index: usize) -> Option<Morpheme>;
+    /// `index` is the ID in the double array - 1
+    fn get_homonyms(&self, index: usize) -> Option<WordIds<'_>>;
+    fn get_unknown_class(&self, class: &str) -> Option<WordIds<'_>>;
+    fn get_transition_cost(&self, left: usize, right: usize) -> Option<i16>;
+}
+impl Tables for IPADic {
+    fn get_vocabulary(&self, wid: usize) -> Option<usize> {
+        self.vocabulary.get(wid).copied()
+    }
+
+    fn get_unknown_vocabulary(&self, wid: usize) -> Option<usize> {
+        self.unknown_vocabulary.get(wid).copied()
+    }
+
+    fn get_morpheme(&self, index: usize) -> Option<Morpheme> {
+        self.vocabulary_index.get_index(index).copied()
+    }
+
+    fn get_homonyms(&self, index: usize) -> Option<WordIds<'_>> {
+        self.homonyms.get(index).map(|wids| WordIds::Owned(wids))
+    }
+
+    fn get_unknown_class(&self, class: &str) -> Option<WordIds<'_>> {
         self.unknown_classes
             .get(class)
-            .unwrap()
-            .iter()
-            .map(|wid| (*wid, self.unknown_vocabulary.get(*wid).unwrap()))
-            .map(|(wid, idx)| (wid, self.vocabulary_index.get_index(*idx).unwrap()))
-            .collect::<Vec<_>>()
-    }
-
-    fn transition_cost(&self, left: &usize, right: &usize) -> Option<&i16> {
-        if let Some(rights) = self.matrix.get(*left) {
-            if let Some(cost) = rights.get(*right) {
-                return Some(cost);
-            }
-        }
-        None
+            .map(|wids| WordIds::Owned(wids))
     }
 
-    fn occurrence_cost(&self, wid: &usize) -> Option<i16> {
-        self.get_known_morpheme(wid).map(|w| w.cost)
+    fn get_transition_cost(&self, left: usize, right: usize) -> Option<i16> {
+        self.matrix
+            .get(left)
+            .and_then(|rights| rights.get(right))
+            .copied()
     }
 }
+impl Tables for ArchivedIPADic {
+    fn get_vocabulary(&self, wid: usize) -> Option<usize> {
+        self.vocabulary.get(wid).map(|idx| *idx as usize)
+    }
+
+    fn get_unknown_vocabulary(&self, wid: usize) -> Option<usize> {
+        self.unknown_vocabulary.get(wid).map(|idx| *idx as usize)
+    }
+
+    fn get_morpheme(&self, index: usize) -> Option<Morpheme> {
+        self.vocabulary_index.get_index(index).map(to_morpheme)
+    }
+
+    fn get_homonyms(&self, index: usize) -> Option<WordIds<'_>> {
+        self.homonyms.get(index).map(|wids| WordIds::Archived(wids))
+    }
+
+    fn get_unknown_class(&self, class: &str) -> Option<WordIds<'_>> {
+        self.unknown_classes
+            .get(class)
+            .map(|wids| WordIds::Archived(wids))
+    }
+
+    fn get_transition_cost(&self, left: usize, right: usize) -> Option<i16> {
+        self.matrix
+            .get(left)
+            .and_then(|rights| rights.get(right))
+            .copied()
+    }
+}
+
+fn get_known_morpheme<T: Tables>(dict: &T, wid: &usize) -> Result<Morpheme> {
+    dict.get_vocabulary(*wid)
+        .and_then(|idx| dict.get_morpheme(idx))
+        .ok_or(Error::KnownMorphemeNotFound(*wid))
+}
+
+fn get_unknown_morpheme<T: Tables>(dict: &T, wid: &usize) -> Result<Morpheme> {
+    dict.get_unknown_vocabulary(*wid)
+        .and_then(|idx| dict.get_morpheme(idx))
+        .ok_or(Error::UnknownMorphemeNotFound(*wid))
+}
+
+fn resolve_homonyms<'a, T: Tables>(dict: &'a T, id: &usize) -> Result<WordIds<'a>> {
+    id.checked_sub(1)
+        .and_then(|idx| dict.get_homonyms(idx))
+        .ok_or(Error::HomonymsNotFound(*id))
+}
+
+fn get_unknown_morphemes_by_class<T: Tables>(
+    dict: &T,
+    class: &str,
+) -> Result<Vec<(usize, Morpheme)>> {
+    dict.get_unknown_class(class)
+        .ok_or_else(|| Error::UnknownClassNotFound(class.to_string()))?
+        .iter()
+        .map(|wid| Ok((wid, get_unknown_morpheme(dict, &wid)?)))
+        .collect()
+}
+
+fn transition_cost<T: Tables>(dict: &T, left: &usize, right: &usize) -> Result<i16> {
+    dict.get_transition_cost(*left, *right)
+        .ok_or(Error::TransitionCostNotFound(*left, *right))
+}
+
+fn to_morpheme(archived: &ArchivedMorpheme) -> Morpheme {
+    Morpheme::new(
+        archived.left_context_id as usize,
+        archived.right_context_id as usize,
+        archived.cost,
+    )
+}
+
 impl IPADic {
     pub fn from(
         vocabulary: Vec<MorphemeIndex>,
         homonyms: Vec<Vec<usize>>,
         classes: CharClassifier,
         matrix: Vec<Vec<i16>>,
-        unknown_classes: HashMap<String, Vec<usize>>,
+        unknown_classes: IndexMap<String, Vec<usize>>,
         unknown_vocabulary: Vec<MorphemeIndex>,
         vocabulary_index: IndexSet<Morpheme>,
     ) -> IPADic {
@@ -93,13 +228,17 @@ impl IPADic {
         }
     }
 
-    pub fn shrink_to_wids(&mut self, wids: &Vec<usize>) {
-        let set: HashSet<usize> = HashSet::from_iter(wids.iter().cloned());
-        for idx in 0..self.homonyms.len() {
-            if set.contains(&idx) {
-                continue;
-            }
-            self.homonyms[idx] = vec![];
-        }
+    pub fn char_classifier(&self) -> &CharClassifier {
+        &self.classes
+    }
+
+    /// index = right context ID of the left word, left context ID of the right word
+    pub fn matrix(&self) -> &Vec<Vec<i16>> {
+        &self.matrix
+    }
+
+    /// key = char class, value = IDs of the unknown words
+    pub fn unknown_classes(&self) -> &IndexMap<String, Vec<usize>> {
+        &self.unknown_classes
     }
 }
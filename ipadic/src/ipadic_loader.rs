@@ -1,14 +1,15 @@
 use super::ipadic::IPADic;
 use csv::ReaderBuilder;
-use encoding_rs::EUC_JP;
+use encoding_rs::{Encoding, EUC_JP};
 use glob::glob;
 use goya::char_class::{CharClass, CharClassifier, CharDefinition, InvokeTiming};
 use goya::morpheme::Morpheme;
+use goya::user_dictionary::UserDictionary;
 use goya::word_features::WordFeaturesMap;
-use indexmap::IndexSet;
+use indexmap::{IndexMap, IndexSet};
 use regex::Regex;
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
@@ -22,101 +23,153 @@ const COL_COST: usize = 3; // コスト
 pub struct LoadResult {
     pub ipadic: IPADic,
     pub word_set: WordFeaturesMap,
+    /// key = ID in the double array, value = surface form shared by the homonyms
     pub surfaces: HashMap<usize, String>,
+    /// Charset of the source files, e.g. EUC-JP
+    pub charset: &'static str,
 }
 
 pub struct IPADicLoader {}
 impl IPADicLoader {
     pub fn load(&self, dir: &str) -> Result<LoadResult, Box<dyn Error>> {
-        let classes = load_chars(Path::new(dir).join("char.def"))?;
-        let matrix = load_matrix(Path::new(dir).join("matrix.def"))?;
-        let unknown = load_unknown(Path::new(dir).join("unk.def"))?;
-        let csv_pattern = Path::new(dir).join("*.csv");
-        let csv_pattern = csv_pattern.to_str().ok_or("Failed to build glob pattern")?;
-
-        let mut vocabulary_index: IndexSet<Morpheme> = IndexSet::new();
-        let mut surfaces = HashMap::new();
-        let mut known_features = HashMap::new();
-        let mut vocabulary = HashMap::new();
-        let mut tmp_homonyms = HashMap::new();
-        let mut id: usize = 1;
-        for path in glob(csv_pattern)? {
-            for row in load_words_csv(path?)? {
-                surfaces.insert(id, row.surface_form.to_string());
-                known_features.insert(id, row.features.clone());
-                tmp_homonyms
-                    .entry(row.surface_form.to_string())
-                    .or_insert_with(Vec::new)
-                    .push(id);
+        load_dictionary(dir, EUC_JP)
+    }
 
-                let (idx, _) = vocabulary_index.insert_full(row.into());
-                vocabulary.insert(id, idx);
-                id += 1;
-            }
-        }
-        let mut homonyms: HashMap<usize, Vec<usize>> = HashMap::new();
-        for wids in tmp_homonyms.values() {
-            for wid in wids.iter() {
-                homonyms.insert(*wid, wids.iter().copied().collect());
-            }
+    /// Loads a user dictionary CSV in the same format as the IPADIC CSVs.
+    /// The file is read as UTF-8 when it's valid, otherwise as EUC-JP.
+    pub fn load_user_dictionary<P>(&self, path: P) -> Result<UserDictionary, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = fs::read(path)?;
+        match std::str::from_utf8(&bytes) {
+            Ok(utf8) => self.parse_user_dictionary(utf8),
+            Err(_) => self.parse_user_dictionary(&EUC_JP.decode(&bytes).0),
         }
+    }
+
+    pub fn parse_user_dictionary(&self, csv: &str) -> Result<UserDictionary, Box<dyn Error>> {
+        let words = parse_words_csv(csv)?
+            .into_iter()
+            .map(|row| {
+                (
+                    row.surface_form.to_string(),
+                    row.clone().into(),
+                    row.features,
+                )
+            })
+            .collect();
+        Ok(UserDictionary::from(words))
+    }
+}
+
+/// Loads a dictionary in the MeCab format, which IPADIC, UniDic and mecab-ko-dic share
+pub(crate) fn load_dictionary(
+    dir: &str,
+    encoding: &'static Encoding,
+) -> Result<LoadResult, Box<dyn Error>> {
+    let classes = load_chars(Path::new(dir).join("char.def"), encoding)?;
+    let matrix = load_matrix(Path::new(dir).join("matrix.def"), encoding)?;
+    let unknown = load_unknown(Path::new(dir).join("unk.def"), encoding)?;
+
+    let csv_pattern = Path::new(dir).join("*.csv");
+    let csv_pattern = csv_pattern.to_str().ok_or("Failed to build glob pattern")?;
+    // Word IDs depend on the order of the files, which must not depend on the file system
+    let mut paths = glob(csv_pattern)?.collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    let mut words = vec![];
+    for path in paths {
+        words.append(&mut load_words_csv(path, encoding)?);
+    }
+    Ok(build_dictionary(
+        words,
+        unknown,
+        classes,
+        matrix,
+        encoding.name(),
+    ))
+}
 
-        let mut unknown_vocabulary = HashMap::new();
-        let mut unknown_features = HashMap::new();
-        let mut unknown_classes = HashMap::new();
-        let mut id = 1;
-        for (class, words) in unknown.into_iter() {
-            for row in words {
-                unknown_features.insert(id, row.features.clone());
-                let (idx, _) = vocabulary_index.insert_full(row.into());
-                unknown_vocabulary.insert(id, idx);
-                unknown_classes
-                    .entry(class.to_string())
-                    .or_insert_with(Vec::new)
-                    .push(id);
-                id += 1;
-            }
+/// Builds the dictionary from the parsed sources. Word IDs are assigned in the order of `words`
+/// and every word is reachable through the homonyms of its surface form
+pub(crate) fn build_dictionary(
+    words: Vec<CSVRow>,
+    unknown: IndexMap<String, Vec<CSVRow>>,
+    classes: CharClassifier,
+    matrix: Vec<Vec<i16>>,
+    charset: &'static str,
+) -> LoadResult {
+    let mut vocabulary_index: IndexSet<Morpheme> = IndexSet::new();
+    let mut known_features = vec![];
+    let mut vocabulary = vec![];
+    let mut tmp_homonyms: IndexMap<String, Vec<usize>> = IndexMap::new();
+    for (wid, row) in words.into_iter().enumerate() {
+        known_features.push(row.features.clone());
+        tmp_homonyms
+            .entry(row.surface_form.to_string())
+            .or_default()
+            .push(wid);
+
+        let (idx, _) = vocabulary_index.insert_full(row.into());
+        vocabulary.push(idx);
+    }
+    let mut surfaces = HashMap::new();
+    let mut homonyms = vec![];
+    for (idx, (surface, wids)) in tmp_homonyms.into_iter().enumerate() {
+        surfaces.insert(idx + 1, surface);
+        homonyms.push(wids);
+    }
+
+    let mut unknown_vocabulary = vec![];
+    let mut unknown_features = vec![];
+    let mut unknown_classes = IndexMap::new();
+    for (class, words) in unknown.into_iter() {
+        for row in words {
+            unknown_classes
+                .entry(class.to_string())
+                .or_insert_with(Vec::new)
+                .push(unknown_vocabulary.len());
+            unknown_features.push(row.features.clone());
+            let (idx, _) = vocabulary_index.insert_full(row.into());
+            unknown_vocabulary.push(idx);
         }
+    }
 
-        let word_set = WordFeaturesMap::new(
-            map_to_vec(known_features, Vec::new),
-            map_to_vec(unknown_features, Vec::new),
-        );
-        let ipadic = IPADic::from(
-            map_to_vec(vocabulary, || 0),
-            map_to_vec(homonyms, Vec::new),
-            classes,
-            matrix,
-            unknown_classes,
-            map_to_vec(unknown_vocabulary, || 0),
-            vocabulary_index,
-        );
-        let ret = LoadResult {
-            word_set,
-            ipadic,
-            surfaces,
-        };
-        Ok(ret)
+    let word_set = WordFeaturesMap::new(known_features, unknown_features);
+    let ipadic = IPADic::from(
+        vocabulary,
+        homonyms,
+        classes,
+        matrix,
+        unknown_classes,
+        unknown_vocabulary,
+        vocabulary_index,
+    );
+    LoadResult {
+        word_set,
+        ipadic,
+        surfaces,
+        charset,
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
-struct CSVRow {
+pub(crate) struct CSVRow {
     /// 表層形
     /// https://taku910.github.io/mecab/dic-detail.html
-    surface_form: String,
+    pub(crate) surface_form: String,
     /// 左文脈ID (単語を左から見たときの文脈 ID)
     /// https://taku910.github.io/mecab/dic-detail.html
-    left_context_id: usize,
+    pub(crate) left_context_id: usize,
     /// 右文脈ID (単語を右から見たときの文脈 ID)
     /// https://taku910.github.io/mecab/dic-detail.html
-    right_context_id: usize,
+    pub(crate) right_context_id: usize,
     /// 単語コスト (小さいほど出現しやすい)
     /// コスト値は short int (16bit 整数) の範囲におさめる必要があります.
-    cost: i16,
+    pub(crate) cost: i16,
     /// 5カラム目以降は, ユーザ定義の CSV フィールドです. 基本的に どんな内容でも CSV の許す限り追加することができます.
     /// https://taku910.github.io/mecab/dic-detail.html
-    features: Vec<String>,
+    pub(crate) features: Vec<String>,
 }
 impl From<CSVRow> for Morpheme {
     fn from(row: CSVRow) -> Self {
@@ -128,23 +181,31 @@ impl From<CSVRow> for Morpheme {
     }
 }
 
-fn load_words_csv<P>(path: P) -> Result<Vec<CSVRow>, Box<dyn Error>>
+fn load_words_csv<P>(path: P, encoding: &'static Encoding) -> Result<Vec<CSVRow>, Box<dyn Error>>
 where
     P: AsRef<Path>,
 {
-    let eucjp = fs::read(path)?;
-    let (utf8, _, _) = EUC_JP.decode(&eucjp);
+    let bytes = fs::read(path)?;
+    let (utf8, _, _) = encoding.decode(&bytes);
+    parse_words_csv(&utf8)
+}
+
+fn parse_words_csv(text: &str) -> Result<Vec<CSVRow>, Box<dyn Error>> {
     let mut rdr = ReaderBuilder::new()
         .has_headers(false)
-        .from_reader(utf8.as_bytes());
+        .flexible(true)
+        .from_reader(text.as_bytes());
     let mut words = vec![];
     for row in rdr.records() {
         let row = row?;
+        if row.len() <= COL_COST {
+            return Err(format!("too few columns in line {}", words.len() + 1).into());
+        }
         words.push(CSVRow {
             surface_form: row[COL_SURFACE_FORM].to_string(),
-            left_context_id: row[COL_LEFT_CONTEXT_ID].parse::<usize>().unwrap(),
-            right_context_id: row[COL_RIGHT_CONTEXT_ID].parse::<usize>().unwrap(),
-            cost: row[COL_COST].parse::<i16>().unwrap(),
+            left_context_id: row[COL_LEFT_CONTEXT_ID].parse::<usize>()?,
+            right_context_id: row[COL_RIGHT_CONTEXT_ID].parse::<usize>()?,
+            cost: row[COL_COST].parse::<i16>()?,
             features: row
                 .iter()
                 .skip(COL_COST + 1)
@@ -155,12 +216,12 @@ where
     Ok(words)
 }
 
-fn load_chars<P>(path: P) -> Result<CharClassifier, Box<dyn Error>>
+fn load_chars<P>(path: P, encoding: &'static Encoding) -> Result<CharClassifier, Box<dyn Error>>
 where
     P: AsRef<Path>,
 {
-    let eucjp = fs::read(path)?;
-    let (utf8, _, _) = EUC_JP.decode(&eucjp);
+    let bytes = fs::read(path)?;
+    let (utf8, _, _) = encoding.decode(&bytes);
     let lines = utf8
         .lines()
         .filter(|line| !line.is_empty() && !line.starts_with('#'))
@@ -171,7 +232,7 @@ where
         let parts = line.trim().split_ascii_whitespace().collect::<Vec<_>>();
         !parts[0].starts_with("0x")
     });
-    let mut chars = HashMap::new();
+    let mut chars = IndexMap::new();
     for line in head {
         let parts = line.trim().split_ascii_whitespace().collect::<Vec<_>>();
         let kind = parts[0].to_owned();
@@ -190,7 +251,6 @@ where
                 timing,
                 group_by_same_kind,
                 len,
-                compatibilities: HashSet::new(),
             },
         );
     }
@@ -204,33 +264,31 @@ where
         let parts = line.trim().split_ascii_whitespace().collect::<Vec<_>>();
         let range = parts[0]
             .split("..")
-            .map(|c| u32::from_str_radix(&c[2..], 16).unwrap())
-            .map(|c| char::from_u32(c).unwrap())
-            .collect::<Vec<_>>();
+            .map(|c| u32::from_str_radix(&c[2..], 16))
+            .collect::<Result<Vec<_>, _>>()?;
         let range = if range.len() > 1 {
-            (range[0] as u32, range[1] as u32)
+            (range[0], range[1])
         } else {
-            (range[0] as u32, range[0] as u32)
+            (range[0], range[0])
         };
         let class = parts[1];
         let compatibilities = parts
             .iter()
             .skip(2)
             .map(|s| s.to_string())
-            .collect::<HashSet<_>>();
-        chars.get_mut(class).unwrap().compatibilities = compatibilities;
-        ranges.push(CharClass::from(range, class.to_string()));
+            .collect::<IndexSet<_>>();
+        ranges.push(CharClass::from(range, class.to_string(), compatibilities));
     }
 
     Ok(CharClassifier::from(chars, ranges))
 }
 
-fn load_matrix<P>(path: P) -> Result<Vec<Vec<i16>>, Box<dyn Error>>
+fn load_matrix<P>(path: P, encoding: &'static Encoding) -> Result<Vec<Vec<i16>>, Box<dyn Error>>
 where
     P: AsRef<Path>,
 {
-    let eucjp = fs::read(path)?;
-    let (utf8, _, _) = EUC_JP.decode(&eucjp);
+    let bytes = fs::read(path)?;
+    let (utf8, _, _) = encoding.decode(&bytes);
     let mut lines = utf8.lines();
     let size = lines
         .next()
@@ -249,24 +307,80 @@ where
     Ok(matrix)
 }
 
-fn load_unknown<P>(path: P) -> Result<HashMap<String, Vec<CSVRow>>, Box<dyn Error>>
+fn load_unknown<P>(
+    path: P,
+    encoding: &'static Encoding,
+) -> Result<IndexMap<String, Vec<CSVRow>>, Box<dyn Error>>
 where
     P: AsRef<Path>,
 {
-    let words = load_words_csv(path)?;
-    let mut map = HashMap::<String, Vec<CSVRow>>::new();
+    Ok(group_by_class(load_words_csv(path, encoding)?))
+}
+
+/// The surface forms of unknown words are the char classes, which keep the order in unk.def
+pub(crate) fn group_by_class(words: Vec<CSVRow>) -> IndexMap<String, Vec<CSVRow>> {
+    let mut map = IndexMap::<String, Vec<CSVRow>>::new();
     for w in words.into_iter() {
-        map.entry(w.surface_form.to_string())
-            .or_insert_with(Vec::new)
-            .push(w);
+        map.entry(w.surface_form.to_string()).or_default().push(w);
     }
-    Ok(map)
+    map
 }
 
-fn map_to_vec<T: Clone>(map: HashMap<usize, T>, default: impl Fn() -> T) -> Vec<T> {
-    let mut ret = vec![default(); map.len() + 1];
-    for (idx, value) in map.into_iter() {
-        ret[idx] = value;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use goya::dictionary::Dictionary;
+
+    #[test]
+    fn parse_user_dictionary() {
+        let loader = IPADicLoader {};
+        let dict = loader
+            .parse_user_dictionary(
+                "ゴヤ,1285,1285,1000,名詞,固有名詞\nゴーヤ,1285,1285,2000,名詞\n",
+            )
+            .unwrap();
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.get(&0).unwrap(), &Morpheme::new(1285, 1285, 1000));
+        assert_eq!(
+            dict.get_features(&0).unwrap(),
+            &vec![String::from("名詞"), String::from("固有名詞")]
+        );
+        assert!(loader.parse_user_dictionary("ゴヤ,1285,1285\n").is_err());
+        assert!(loader.parse_user_dictionary("ゴヤ,a,1285,1000\n").is_err());
+    }
+
+    #[test]
+    fn build_dictionary_keeps_homonyms() {
+        let words = parse_words_csv("あ,1,1,10,a\nい,2,2,20,b\nあ,3,3,30,c\n").unwrap();
+        let loaded = build_dictionary(
+            words,
+            IndexMap::new(),
+            CharClassifier::from(IndexMap::new(), vec![]),
+            vec![],
+            "UTF-8",
+        );
+        let mut surfaces = loaded.surfaces.into_iter().collect::<Vec<_>>();
+        surfaces.sort();
+        assert_eq!(
+            surfaces,
+            vec![(1, String::from("あ")), (2, String::from("い"))]
+        );
+        let dict = loaded.ipadic;
+        let homonyms = |id| {
+            dict.resolve_homonyms(&id)
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(homonyms(1), vec![0, 2]);
+        assert_eq!(homonyms(2), vec![1]);
+        assert!(dict.resolve_homonyms(&0).is_err());
+        assert_eq!(
+            dict.get_known_morpheme(&2).unwrap(),
+            Morpheme::new(3, 3, 30)
+        );
+        assert!(dict.get_known_morpheme(&3).is_err());
+        assert_eq!(loaded.word_set.get_known(&0).unwrap(), vec!["a"]);
+        assert!(loaded.word_set.get_known(&3).is_none());
     }
-    ret
 }
@@ -1,2 +1,6 @@
+pub mod dump;
 pub mod ipadic;
 pub mod ipadic_loader;
+pub mod mecab_binary_exporter;
+pub mod mecab_binary_loader;
+pub mod utf8_dic_loader;
@@ -0,0 +1,325 @@
+use super::dump::{join_csv, known_words, unknown_words, user_words};
+use super::ipadic::IPADic;
+use super::mecab_binary_loader::{
+    CHAR_CLASS_NAME_SIZE, CHAR_INFO_SIZE, DICTIONARY_MAGIC_ID, DICTIONARY_VERSION,
+};
+use goya::char_class::{CharDefinition, InvokeTiming};
+use goya::double_array::SystemDoubleArray;
+use goya::morpheme::Morpheme;
+use goya::user_dictionary::UserDictionary;
+use goya::word_features::WordFeaturesMap;
+use goya::Error as GoyaError;
+use indexmap::IndexMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const DICTIONARY_TYPE_SYSTEM: u32 = 0;
+const DICTIONARY_TYPE_UNKNOWN: u32 = 2;
+const CHARSET: &str = "utf-8";
+
+/// Writes a compiled dictionary in the binary format of MeCab, i.e. sys.dic, unk.dic, matrix.bin, char.bin and dicrc.
+/// MeCab can use the directory with `mecab -d`.
+pub struct MeCabBinaryExporter {}
+impl MeCabBinaryExporter {
+    /// The words of the user dictionary are merged into sys.dic.
+    /// They precede the system words of the same surface form to win ties like `Lattice::parse`.
+    pub fn export(
+        &self,
+        da: &SystemDoubleArray,
+        dict: &IPADic,
+        features: &WordFeaturesMap,
+        user: Option<&UserDictionary>,
+        dir: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let matrix = dict.matrix();
+        let lsize = matrix.len();
+        let rsize = matrix.first().map_or(0, |row| row.len());
+
+        let mut known = match user {
+            Some(user) => user_words(user)?,
+            None => vec![],
+        };
+        known.extend(known_words(da, dict, features)?);
+        // Stable to keep the user words first
+        known.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        let known = known
+            .into_iter()
+            .map(|(surface, morpheme, f)| (surface, morpheme, join_csv(&f)))
+            .collect::<Vec<_>>();
+        let unknown = unknown_words(dict, features)?
+            .into_iter()
+            .map(|(surface, morpheme, f)| (surface, morpheme, join_csv(&f)))
+            .collect::<Vec<_>>();
+
+        let dir = Path::new(dir);
+        fs::create_dir_all(dir)?;
+        fs::write(
+            dir.join("sys.dic"),
+            dictionary_to_bytes(&known, DICTIONARY_TYPE_SYSTEM, lsize, rsize)?,
+        )?;
+        fs::write(
+            dir.join("unk.dic"),
+            dictionary_to_bytes(&unknown, DICTIONARY_TYPE_UNKNOWN, lsize, rsize)?,
+        )?;
+        fs::write(dir.join("matrix.bin"), matrix_to_bytes(matrix)?)?;
+        fs::write(
+            dir.join("char.bin"),
+            chars_to_bytes(dict.char_classifier().definitions(), dict)?,
+        )?;
+        // The number of features of BOS/EOS should be the same as the words
+        let size = known.first().map_or(1, |(_, _, f)| f.split(',').count());
+        fs::write(
+            dir.join("dicrc"),
+            format!(
+                "cost-factor = 800\nbos-feature = BOS/EOS{}\nconfig-charset = {}\n",
+                ",*".repeat(size.saturating_sub(1)),
+                CHARSET
+            ),
+        )?;
+        Ok(())
+    }
+}
+
+/// (surface form, morpheme, features joined with commas)
+type Row = (String, Morpheme, String);
+
+/// `rows` must be sorted by the surface forms
+fn dictionary_to_bytes(
+    rows: &[Row],
+    dictionary_type: u32,
+    lsize: usize,
+    rsize: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut keys: Vec<DartsKey> = vec![];
+    let mut tokens = vec![];
+    let mut features = vec![];
+    for (idx, (surface, morpheme, feature)) in rows.iter().enumerate() {
+        // The upper 24 bits are the index of the first token and the lower 8 bits are the number of them
+        match keys.last_mut() {
+            Some((key, value)) if key == surface.as_bytes() => {
+                if *value & 0xff == 0xff {
+                    return Err(format!("too many homonyms of {}", surface).into());
+                }
+                *value += 1;
+            }
+            _ => keys.push((surface.as_bytes().to_vec(), ((idx as u32) << 8) | 1)),
+        }
+        tokens.extend_from_slice(&(morpheme.left_context_id as u16).to_le_bytes());
+        tokens.extend_from_slice(&(morpheme.right_context_id as u16).to_le_bytes());
+        // The pos-id is lost since neither the loaders read pos-id.def nor `Morpheme` keeps one.
+        // It's 0 like mecab-dict-index without pos-id.def, so only `%h` in the output format of MeCab differs.
+        tokens.extend_from_slice(&0u16.to_le_bytes());
+        tokens.extend_from_slice(&morpheme.cost.to_le_bytes());
+        tokens.extend_from_slice(&(features.len() as u32).to_le_bytes());
+        tokens.extend_from_slice(&0u32.to_le_bytes()); // compound
+        features.extend_from_slice(feature.as_bytes());
+        features.push(0);
+    }
+    let mut darts = vec![];
+    for (base, check) in build_darts(&keys) {
+        darts.extend_from_slice(&base.to_le_bytes());
+        darts.extend_from_slice(&check.to_le_bytes());
+    }
+
+    let mut bytes = vec![];
+    for value in [
+        0, // magic, which depends on the file size
+        DICTIONARY_VERSION,
+        dictionary_type,
+        rows.len() as u32,
+        lsize as u32,
+        rsize as u32,
+        darts.len() as u32,
+        tokens.len() as u32,
+        features.len() as u32,
+        0,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&name_to_bytes(CHARSET)?);
+    bytes.append(&mut darts);
+    bytes.append(&mut tokens);
+    bytes.append(&mut features);
+    let magic = bytes.len() as u32 ^ DICTIONARY_MAGIC_ID;
+    bytes[..4].copy_from_slice(&magic.to_le_bytes());
+    Ok(bytes)
+}
+
+/// (key, value)
+type DartsKey = (Vec<u8>, u32);
+/// (code, keys which have the code at the depth)
+type Sibling<'a> = (usize, &'a [DartsKey]);
+
+/// Builds Darts, the double array of MeCab, from the sorted keys.
+/// A child of the node whose base is `b` is at `b + byte + 1` and its check is `b`.
+/// The value is at `b` with a negative base.
+fn build_darts(keys: &[DartsKey]) -> Vec<(i32, u32)> {
+    let mut builder = DartsBuilder {
+        // The root has no children when there is no key
+        units: vec![(1, 0)],
+        used: vec![true],
+        used_bases: vec![],
+        next_check_pos: 1,
+    };
+    builder.insert(keys, 0, 0);
+    // MeCab doesn't check the bounds of the array
+    let max_base = builder
+        .units
+        .iter()
+        .map(|(base, _)| *base)
+        .max()
+        .unwrap_or(0);
+    builder.resize(max_base.max(0) as usize + 257);
+    builder.units
+}
+
+struct DartsBuilder {
+    units: Vec<(i32, u32)>,
+    used: Vec<bool>,
+    used_bases: Vec<bool>,
+    next_check_pos: usize,
+}
+impl DartsBuilder {
+    fn resize(&mut self, len: usize) {
+        if self.units.len() < len {
+            self.units.resize(len, (0, 0));
+            self.used.resize(len, false);
+        }
+    }
+
+    fn insert(&mut self, keys: &[DartsKey], depth: usize, position: usize) {
+        let mut siblings: Vec<Sibling> = vec![];
+        let mut left = 0;
+        for right in 1..=keys.len() {
+            let code = |idx: usize| keys[idx].0.get(depth).map_or(0, |b| *b as usize + 1);
+            if right < keys.len() && code(right) == code(left) {
+                continue;
+            }
+            siblings.push((code(left), &keys[left..right]));
+            left = right;
+        }
+        if siblings.is_empty() {
+            return;
+        }
+
+        let base = self.find_base(&siblings);
+        self.units[position].0 = base as i32;
+        for (code, _) in siblings.iter() {
+            self.used[base + code] = true;
+            self.units[base + code].1 = base as u32;
+        }
+        for (code, keys) in siblings {
+            if code == 0 {
+                self.units[base].0 = -(keys[0].1 as i32) - 1;
+            } else {
+                self.insert(keys, depth + 1, base + code);
+            }
+        }
+    }
+
+    fn find_base(&mut self, siblings: &[Sibling]) -> usize {
+        let first = siblings[0].0;
+        let last = siblings[siblings.len() - 1].0;
+        let mut position = self.next_check_pos.max(first + 1);
+        let mut occupied = 0;
+        let base = loop {
+            self.resize(position + last + 1);
+            if self.used[position] {
+                occupied += 1;
+                position += 1;
+                continue;
+            }
+            let base = position - first;
+            if !self.used_bases.get(base).copied().unwrap_or(false)
+                && siblings.iter().all(|(code, _)| !self.used[base + code])
+            {
+                break base;
+            }
+            position += 1;
+        };
+        // Skip the dense area like Darts
+        if occupied as f64 / (position - self.next_check_pos + 1) as f64 >= 0.95 {
+            self.next_check_pos = position;
+        }
+        if self.used_bases.len() <= base {
+            self.used_bases.resize(base + 1, false);
+        }
+        self.used_bases[base] = true;
+        base
+    }
+}
+
+fn matrix_to_bytes(matrix: &[Vec<i16>]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let lsize = matrix.len();
+    let rsize = matrix.first().map_or(0, |row| row.len());
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&u16::try_from(lsize)?.to_le_bytes());
+    bytes.extend_from_slice(&u16::try_from(rsize)?.to_le_bytes());
+    // Costs are stored in the order of the right context ID
+    for right in 0..rsize {
+        for row in matrix.iter() {
+            bytes.extend_from_slice(&row[right].to_le_bytes());
+        }
+    }
+    Ok(bytes)
+}
+
+fn chars_to_bytes(
+    definitions: &IndexMap<String, CharDefinition>,
+    dict: &IPADic,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut names = definitions.keys().collect::<Vec<_>>();
+    names.sort();
+    let id = |name: &str| {
+        names
+            .iter()
+            .position(|n| *n == name)
+            .ok_or_else(|| GoyaError::CharClassNotFound(name.to_string()))
+    };
+    let info = |name: &str, kind: u32| -> Result<u32, GoyaError> {
+        let def = &definitions[name];
+        let invoke = matches!(def.timing, InvokeTiming::Always);
+        Ok((kind & 0x3ffff)
+            | ((id(name)? as u32) << 18)
+            | ((def.len.min(0xf) as u32) << 26)
+            | ((def.group_by_same_kind as u32) << 30)
+            | ((invoke as u32) << 31))
+    };
+
+    let default = info("DEFAULT", 1 << id("DEFAULT")?)?;
+    let mut infos = vec![default; CHAR_INFO_SIZE];
+    for class in dict.char_classifier().ranges() {
+        let mut kind = 1 << id(class.class())?;
+        for name in class.compatibilities() {
+            kind |= 1 << id(name)?;
+        }
+        let value = info(class.class(), kind)?;
+        let (start, end) = class.range();
+        let end = (end as usize).min(CHAR_INFO_SIZE - 1);
+        if start as usize <= end {
+            infos[start as usize..=end].fill(value);
+        }
+    }
+
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    for name in names.iter() {
+        bytes.extend_from_slice(&name_to_bytes(name)?);
+    }
+    for value in infos {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// A NUL-terminated string in the fixed size
+fn name_to_bytes(name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if name.len() >= CHAR_CLASS_NAME_SIZE {
+        return Err(format!("{} is too long", name).into());
+    }
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.resize(CHAR_CLASS_NAME_SIZE, 0);
+    Ok(bytes)
+}
@@ -0,0 +1,297 @@
+use super::ipadic_loader::{build_dictionary, group_by_class, CSVRow, LoadResult};
+use csv::ReaderBuilder;
+use encoding_rs::Encoding;
+use goya::char_class::{CharClass, CharClassifier, CharDefinition, InvokeTiming};
+use indexmap::{IndexMap, IndexSet};
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// `DictionaryMagicID` of MeCab
+pub(crate) const DICTIONARY_MAGIC_ID: u32 = 0xef718f77;
+pub(crate) const DICTIONARY_VERSION: u32 = 102;
+/// char.bin has the char info of U+0000 to U+FFFE
+pub(crate) const CHAR_INFO_SIZE: usize = 0xffff;
+pub(crate) const CHAR_CLASS_NAME_SIZE: usize = 32;
+
+/// Loads a dictionary compiled by `mecab-dict-index`, i.e. sys.dic, unk.dic, matrix.bin and char.bin.
+/// It's useful to analyze with the same vocabulary as an existing MeCab installation.
+pub struct MeCabBinaryLoader {}
+impl MeCabBinaryLoader {
+    pub fn load(&self, dir: &str) -> Result<LoadResult, Box<dyn Error>> {
+        let (words, encoding) = load_dictionary(Path::new(dir).join("sys.dic"))?;
+        let (unknown, _) = load_dictionary(Path::new(dir).join("unk.dic"))?;
+        let unknown = group_by_class(unknown);
+        let matrix = load_matrix(Path::new(dir).join("matrix.bin"))?;
+        let classes = load_chars(Path::new(dir).join("char.bin"))?;
+        Ok(build_dictionary(
+            words,
+            unknown,
+            classes,
+            matrix,
+            encoding.name(),
+        ))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn from(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or("unexpected end of file")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_le_bytes(self.read(2)?.try_into()?))
+    }
+
+    fn i16(&mut self) -> Result<i16, Box<dyn Error>> {
+        Ok(i16::from_le_bytes(self.read(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.read(4)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(i32::from_le_bytes(self.read(4)?.try_into()?))
+    }
+
+    /// A NUL-terminated string in the fixed size
+    fn name(&mut self, len: usize) -> Result<String, Box<dyn Error>> {
+        let bytes = self.read(len)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+        Ok(String::from_utf8(bytes[..end].to_vec())?)
+    }
+}
+
+/// Returns the words and the charset of the dictionary
+fn load_dictionary<P>(path: P) -> Result<(Vec<CSVRow>, &'static Encoding), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let bytes = fs::read(path)?;
+    let mut reader = Reader::from(&bytes);
+    let magic = reader.u32()?;
+    if (magic ^ DICTIONARY_MAGIC_ID) as usize != bytes.len() {
+        return Err("dictionary file is broken".into());
+    }
+    let version = reader.u32()?;
+    if version != DICTIONARY_VERSION {
+        return Err(format!("unsupported dictionary version: {}", version).into());
+    }
+    let _type = reader.u32()?;
+    let _lexsize = reader.u32()?;
+    let _lsize = reader.u32()?;
+    let _rsize = reader.u32()?;
+    let dsize = reader.u32()? as usize;
+    let tsize = reader.u32()? as usize;
+    let fsize = reader.u32()? as usize;
+    let _dummy = reader.u32()?;
+    let charset = reader.name(32)?;
+    let encoding = Encoding::for_label(charset.as_bytes())
+        .ok_or_else(|| format!("unsupported charset: {}", charset))?;
+
+    let mut units = Vec::with_capacity(dsize / 8);
+    for _ in 0..dsize / 8 {
+        units.push((reader.i32()?, reader.u32()?));
+    }
+    let mut tokens = vec![];
+    for _ in 0..tsize / 16 {
+        let left_context_id = reader.u16()? as usize;
+        let right_context_id = reader.u16()? as usize;
+        let _posid = reader.u16()?;
+        let cost = reader.i16()?;
+        let feature = reader.u32()? as usize;
+        let _compound = reader.u32()?;
+        tokens.push((left_context_id, right_context_id, cost, feature));
+    }
+    let features = reader.read(fsize)?;
+
+    let mut words = vec![];
+    for (key, value) in darts_entries(&units)? {
+        let (surface_form, _, _) = encoding.decode(&key);
+        // The upper 24 bits are the index of the first token and the lower 8 bits are the number of them
+        let first = (value >> 8) as usize;
+        let len = (value & 0xff) as usize;
+        for idx in first..first + len {
+            let (left_context_id, right_context_id, cost, feature) =
+                *tokens.get(idx).ok_or("token is out of range")?;
+            let feature = features.get(feature..).ok_or("feature is out of range")?;
+            let end = feature
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(feature.len());
+            let (feature, _, _) = encoding.decode(&feature[..end]);
+            words.push(CSVRow {
+                surface_form: surface_form.to_string(),
+                left_context_id,
+                right_context_id,
+                cost,
+                features: parse_features(&feature)?,
+            });
+        }
+    }
+    Ok((words, encoding))
+}
+
+/// (key, value)
+type DartsEntry = (Vec<u8>, u32);
+
+/// Enumerates the keys and values of Darts in the order of the keys.
+/// A child of the node whose base is `b` is at `b + byte + 1` and its check is `b`.
+/// The value is at `b` with a negative base.
+fn darts_entries(units: &[(i32, u32)]) -> Result<Vec<DartsEntry>, Box<dyn Error>> {
+    let root = units.first().ok_or("double array is empty")?.0;
+    let mut entries = vec![];
+    let mut stack = vec![(root, vec![])];
+    while let Some((base, key)) = stack.pop() {
+        if base <= 0 {
+            return Err("double array is broken".into());
+        }
+        let base = base as usize;
+        if let Some((value, check)) = units.get(base) {
+            if *check as usize == base && *value < 0 {
+                entries.push((key.clone(), (-value - 1) as u32));
+            }
+        }
+        // Push in the reverse order to pop the smallest byte first
+        for byte in (0..=255u8).rev() {
+            if let Some((child, check)) = units.get(base + byte as usize + 1) {
+                if *check as usize == base {
+                    let mut key = key.clone();
+                    key.push(byte);
+                    stack.push((*child, key));
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_features(feature: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(feature.as_bytes());
+    match rdr.records().next() {
+        Some(record) => Ok(record?.iter().map(|f| f.to_string()).collect()),
+        None => Ok(vec![]),
+    }
+}
+
+fn load_matrix<P>(path: P) -> Result<Vec<Vec<i16>>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let bytes = fs::read(path)?;
+    let mut reader = Reader::from(&bytes);
+    let lsize = reader.u16()? as usize;
+    let rsize = reader.u16()? as usize;
+    let mut matrix = vec![vec![0; rsize]; lsize];
+    // Costs are stored in the order of the right context ID
+    for right in 0..rsize {
+        for row in matrix.iter_mut() {
+            row[right] = reader.i16()?;
+        }
+    }
+    Ok(matrix)
+}
+
+/// Bit fields of `CharInfo` of MeCab
+struct CharInfo {
+    /// Bits of the classes which the char belongs to
+    kind: u32,
+    default_type: usize,
+    length: usize,
+    group: bool,
+    invoke: bool,
+}
+impl CharInfo {
+    fn from(value: u32) -> CharInfo {
+        CharInfo {
+            kind: value & 0x3ffff,
+            default_type: ((value >> 18) & 0xff) as usize,
+            length: ((value >> 26) & 0xf) as usize,
+            group: (value >> 30) & 1 == 1,
+            invoke: (value >> 31) & 1 == 1,
+        }
+    }
+}
+
+fn load_chars<P>(path: P) -> Result<CharClassifier, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let bytes = fs::read(path)?;
+    let mut reader = Reader::from(&bytes);
+    let csize = reader.u32()? as usize;
+    let mut names = vec![];
+    for _ in 0..csize {
+        names.push(reader.name(CHAR_CLASS_NAME_SIZE)?);
+    }
+    let mut infos = vec![];
+    for _ in 0..CHAR_INFO_SIZE {
+        infos.push(reader.u32()?);
+    }
+    let name = |id: usize| {
+        names
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("char class {} is not defined", id))
+    };
+
+    // The definition of a class is copied to each char of the class
+    let mut chars = IndexMap::new();
+    for value in infos.iter() {
+        let info = CharInfo::from(*value);
+        let class = name(info.default_type)?;
+        chars.entry(class.to_string()).or_insert(CharDefinition {
+            class,
+            timing: if info.invoke {
+                InvokeTiming::Always
+            } else {
+                InvokeTiming::Fallback
+            },
+            group_by_same_kind: info.group,
+            len: info.length,
+        });
+    }
+
+    let mut ranges = vec![];
+    let mut start = 0;
+    for end in 1..=infos.len() {
+        if end < infos.len() && infos[end] == infos[start] {
+            continue;
+        }
+        let info = CharInfo::from(infos[start]);
+        let class = name(info.default_type)?;
+        // Chars which aren't in any range are DEFAULT
+        if class == "DEFAULT" && info.kind == 1 << info.default_type {
+            start = end;
+            continue;
+        }
+        let compatibilities = (0..names.len())
+            .filter(|id| *id != info.default_type && info.kind & (1 << id) != 0)
+            .map(name)
+            .collect::<Result<IndexSet<_>, _>>()?;
+        ranges.push(CharClass::from(
+            (start as u32, end as u32 - 1),
+            class,
+            compatibilities,
+        ));
+        start = end;
+    }
+    Ok(CharClassifier::from(chars, ranges))
+}
@@ -0,0 +1,14 @@
+use super::ipadic_loader::{load_dictionary, LoadResult};
+use encoding_rs::UTF_8;
+use std::error::Error;
+
+/// Loads a dictionary in the same format as IPADIC but encoded in UTF-8,
+/// e.g. UniDic (unidic-cwj, unidic-csj and so on) and mecab-ko-dic for Korean.
+/// The features are kept as they are, e.g. the 29 ones of UniDic or `NNG,*,F,아버지,*,*,*,*` of mecab-ko-dic,
+/// so the loaded dictionary is an `IPADic` as well.
+pub struct Utf8DicLoader {}
+impl Utf8DicLoader {
+    pub fn load(&self, dir: &str) -> Result<LoadResult, Box<dyn Error>> {
+        load_dictionary(dir, UTF_8)
+    }
+}
@@ -0,0 +1,366 @@
+use goya::dictionary::Dictionary;
+use goya::double_array::{DoubleArray, SystemDoubleArray, Trie};
+use goya::id::WordIdentifier;
+use goya::morpheme::Morpheme;
+use goya::user_dictionary::UserDictionary;
+use goya::utf8_double_array::Utf8DoubleArray;
+use goya::Tokenizer;
+use goya_ipadic::dump;
+use goya_ipadic::ipadic::IPADic;
+use goya_ipadic::ipadic_loader::{IPADicLoader, LoadResult};
+use goya_ipadic::mecab_binary_exporter::MeCabBinaryExporter;
+use goya_ipadic::mecab_binary_loader::MeCabBinaryLoader;
+use goya_ipadic::utf8_dic_loader::Utf8DicLoader;
+use rkyv::ser::{serializers::AllocSerializer, Serializer};
+use rkyv::AlignedVec;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const FIXTURES: &str = "tests/fixtures";
+
+fn load() -> Tokenizer<IPADic> {
+    let dir = Path::new(FIXTURES).join("mini-ipadic");
+    tokenizer(IPADicLoader {}.load(dir.to_str().unwrap()).unwrap())
+}
+
+fn tokenizer(loaded: LoadResult) -> Tokenizer<IPADic> {
+    Tokenizer::from(double_array(&loaded.surfaces), loaded.ipadic).with_features(loaded.word_set)
+}
+
+fn double_array(surfaces: &HashMap<usize, String>) -> SystemDoubleArray {
+    SystemDoubleArray::from_sorted_keys(sorted_keys(surfaces).into_iter())
+}
+
+fn sorted_keys(surfaces: &HashMap<usize, String>) -> Vec<(&str, u32)> {
+    let mut keys = surfaces
+        .iter()
+        .map(|(id, surface)| (surface.as_str(), *id as u32))
+        .collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys
+}
+
+/// Formats the best path like `mecab -F '%m\t%H\t%pC\n' -E 'EOS\t%pC\n'`
+fn analyze<D: Dictionary, T: Trie>(tokenizer: &Tokenizer<D, T>, text: &str) -> String {
+    let lattice = tokenizer.parse(text).unwrap();
+    let path = lattice.find_best_path().unwrap();
+    let mut lines = vec![];
+    for token in tokenizer.tokens(&lattice, &path).unwrap() {
+        lines.push(format!(
+            "{}\t{}\t{}",
+            token.surface,
+            token.features.join(","),
+            token.cumulative_cost
+        ));
+    }
+    lines.push(format!("EOS\t{}", lattice.dp[lattice.dp.len() - 1][0].0));
+    lines.join("\n")
+}
+
+/// Splits MeCab output into sentences. Each of them ends with a `EOS` line.
+fn sentences(output: &str) -> Vec<String> {
+    let mut sentences = vec![];
+    let mut lines = vec![];
+    for line in output.lines() {
+        lines.push(line);
+        if line.starts_with("EOS") {
+            sentences.push(lines.join("\n"));
+            lines.clear();
+        }
+    }
+    sentences
+}
+
+#[test]
+fn conforms_to_mecab() {
+    assert_conforms_to_mecab(&load());
+}
+
+#[test]
+fn conforms_to_mecab_with_binary_dictionary() {
+    let dir = Path::new(FIXTURES).join("mini-mecab");
+    let loaded = MeCabBinaryLoader {}.load(dir.to_str().unwrap()).unwrap();
+    assert_conforms_to_mecab(&tokenizer(loaded));
+}
+
+#[test]
+fn conforms_to_mecab_with_exported_dictionary() {
+    let original = load();
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    MeCabBinaryExporter {}
+        .export(
+            original.double_array(),
+            original.system_dictionary(),
+            original.word_features().unwrap(),
+            None,
+            dir,
+        )
+        .unwrap();
+    let loaded = MeCabBinaryLoader {}.load(dir).unwrap();
+    assert_conforms_to_mecab(&tokenizer(loaded));
+}
+
+#[test]
+fn exports_user_dictionary_into_system_dictionary() {
+    let user = || {
+        UserDictionary::from(vec![
+            (
+                String::from("すもももも"),
+                Morpheme::new(1, 1, 100),
+                vec![String::from("名詞"), String::from("ユーザー")],
+            ),
+            (
+                String::from("東京"),
+                Morpheme::new(1, 1, 3003),
+                vec![String::from("名詞"), String::from("ユーザー")],
+            ),
+        ])
+    };
+    let original = load().with_user_dictionary(user());
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    MeCabBinaryExporter {}
+        .export(
+            original.double_array(),
+            original.system_dictionary(),
+            original.word_features().unwrap(),
+            original.user_dictionary(),
+            dir,
+        )
+        .unwrap();
+    let exported = tokenizer(MeCabBinaryLoader {}.load(dir).unwrap());
+    let surfaces = |tokenizer: &Tokenizer<IPADic>, text| {
+        tokenizer
+            .tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.surface, token.features.join(",")))
+            .collect::<Vec<_>>()
+    };
+    for text in ["すもももももももものうち", "東京都に行く", "東京"] {
+        assert_eq!(surfaces(&exported, text), surfaces(&original, text));
+    }
+    assert_eq!(
+        surfaces(&exported, "すもももももももものうち")[0],
+        (String::from("すもももも"), String::from("名詞,ユーザー"))
+    );
+    // The user words precede the system words of the same surface form to win ties
+    assert_eq!(
+        surfaces(&exported, "東京"),
+        vec![(String::from("東京"), String::from("名詞,ユーザー"))]
+    );
+    let words = dump::known_words(
+        exported.double_array(),
+        exported.system_dictionary(),
+        exported.word_features().unwrap(),
+    )
+    .unwrap();
+    let homonyms = words
+        .iter()
+        .filter(|(surface, _, _)| surface == "東京")
+        .map(|(_, _, f)| f.join(","))
+        .collect::<Vec<_>>();
+    assert_eq!(homonyms.len(), 2);
+    assert_eq!(homonyms[0], "名詞,ユーザー");
+}
+
+#[test]
+fn conforms_to_mecab_with_archived_dictionary() {
+    let dir = Path::new(FIXTURES).join("mini-ipadic");
+    let loaded = IPADicLoader {}.load(dir.to_str().unwrap()).unwrap();
+    let da = to_bytes(&double_array(&loaded.surfaces));
+    let dict = to_bytes(&loaded.ipadic);
+    let tokenizer = Tokenizer::from_archived_ref::<IPADic>(&da, &dict)
+        .unwrap()
+        .with_features(loaded.word_set);
+    assert_conforms_to_mecab(&tokenizer);
+}
+
+#[test]
+fn conforms_to_mecab_with_each_double_array() {
+    let dir = Path::new(FIXTURES).join("mini-ipadic");
+    let load = || IPADicLoader {}.load(dir.to_str().unwrap()).unwrap();
+    let loaded = load();
+    let keys = sorted_keys(&loaded.surfaces);
+    let da = DoubleArray::from_sorted_keys(keys.iter().copied());
+    let utf8 = Utf8DoubleArray::from_sorted_keys(keys.iter().copied());
+    assert_eq!(da.entries(), utf8.entries());
+    assert_conforms_to_mecab(&Tokenizer::from(da, &loaded.ipadic).with_features(load().word_set));
+    assert_conforms_to_mecab(&Tokenizer::from(utf8, &loaded.ipadic).with_features(load().word_set));
+}
+
+#[test]
+fn compiles_reproducibly() {
+    let dir = Path::new(FIXTURES).join("mini-ipadic");
+    let compile = || {
+        let loaded = IPADicLoader {}.load(dir.to_str().unwrap()).unwrap();
+        vec![
+            to_bytes(&double_array(&loaded.surfaces)).to_vec(),
+            to_bytes(&loaded.ipadic).to_vec(),
+            to_bytes(&loaded.word_set).to_vec(),
+        ]
+    };
+    let expected = compile();
+    // HashMaps are seeded randomly in each instance
+    for _ in 0..4 {
+        assert_eq!(compile(), expected);
+    }
+}
+
+fn to_bytes<T: rkyv::Serialize<AllocSerializer<256>>>(value: &T) -> AlignedVec {
+    let mut serializer = AllocSerializer::<256>::default();
+    serializer.serialize_value(value).unwrap();
+    serializer.into_serializer().into_inner()
+}
+
+fn assert_conforms_to_mecab<D: Dictionary, T: Trie>(tokenizer: &Tokenizer<D, T>) {
+    let corpus = fs::read_to_string(Path::new(FIXTURES).join("corpus.txt")).unwrap();
+    let expected = fs::read_to_string(Path::new(FIXTURES).join("expected.txt")).unwrap();
+    let expected = sentences(&expected);
+
+    let lines = corpus.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), expected.len());
+    for (text, expected) in lines.iter().zip(expected.iter()) {
+        assert_eq!(&analyze(tokenizer, text), expected, "input: {}", text);
+    }
+}
+
+#[test]
+fn analyzes_any_text() {
+    let tokenizer = load();
+    for text in [
+        "",
+        " ",
+        "\u{0301}も",
+        "👨\u{200D}👩\u{200D}👧",
+        "\u{0}\u{7}\t",
+        "東京\u{FFFF}",
+    ] {
+        let lattice = tokenizer.parse(text).unwrap();
+        let path = lattice.find_best_path().unwrap();
+        let concatenated = tokenizer
+            .tokens(&lattice, &path)
+            .unwrap()
+            .iter()
+            .map(|t| format!("{}{}", t.leading_whitespace, t.surface))
+            .collect::<String>();
+        assert_eq!(
+            concatenated + lattice.trailing_whitespace(),
+            text,
+            "input: {:?}",
+            text
+        );
+        // Only a text of whitespace has no morpheme
+        assert_eq!(
+            path.is_empty(),
+            lattice.trailing_whitespace() == text,
+            "input: {:?}",
+            text
+        );
+    }
+}
+
+#[test]
+fn loads_unidic() {
+    let dir = Path::new(FIXTURES).join("mini-unidic");
+    let tokenizer = tokenizer(Utf8DicLoader {}.load(dir.to_str().unwrap()).unwrap());
+    let tokens = tokenizer.tokenize("東京都に行く").unwrap();
+    assert_eq!(
+        tokens
+            .iter()
+            .map(|t| t.surface.as_str())
+            .collect::<Vec<_>>(),
+        vec!["東京", "都", "に", "行く"]
+    );
+    assert_eq!(tokens[0].features.len(), 29);
+    assert_eq!(tokens[0].features[..3], ["名詞", "固有名詞", "地名"]);
+    // A quoted field may contain commas
+    let tokens = tokenizer.tokenize("すももも").unwrap();
+    assert_eq!(tokens[1].features[25], "動詞%F2@-1,形容詞%F4@-2,名詞%F1");
+}
+
+#[test]
+fn loads_ko_dic() {
+    let dir = Path::new(FIXTURES).join("mini-ko-dic");
+    let tokenizer = tokenizer(Utf8DicLoader {}.load(dir.to_str().unwrap()).unwrap());
+    let analyze = |text| {
+        tokenizer
+            .tokenize(text)
+            .unwrap()
+            .into_iter()
+            .map(|t| format!("{}/{}", t.surface, t.features[0]))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        analyze("아버지가 방에 들어가신다."),
+        vec![
+            "아버지/NNG",
+            "가/JKS",
+            "방/NNG",
+            "에/JKB",
+            "들어가/VV",
+            "신다/EP+EF",
+            "./SF"
+        ]
+    );
+    // Unknown Hangul chars are grouped by the HANGUL class
+    assert_eq!(analyze("서울에"), vec!["서울/UNKNOWN", "에/JKB"]);
+}
+
+#[test]
+fn dumps_sources() {
+    let tokenizer = load();
+    let dir = Path::new(FIXTURES).join("mini-ipadic");
+    let read = |name: &str| {
+        let bytes = fs::read(dir.join(name)).unwrap();
+        let (utf8, _, _) = encoding_rs::EUC_JP.decode(&bytes);
+        let mut lines = utf8.lines().map(|l| l.to_string()).collect::<Vec<_>>();
+        lines.sort();
+        lines
+    };
+    let sorted = |text: String| {
+        let mut lines = text.lines().map(|l| l.to_string()).collect::<Vec<_>>();
+        lines.sort();
+        lines
+    };
+    let dict = tokenizer.system_dictionary();
+    let features = tokenizer.word_features().unwrap();
+
+    let words = dump::known_words(tokenizer.double_array(), dict, features).unwrap();
+    assert_eq!(sorted(dump::to_csv(&words)), read("words.csv"));
+    let unknown = dump::unknown_words(dict, features).unwrap();
+    assert_eq!(sorted(dump::to_csv(&unknown)), read("unk.def"));
+    assert_eq!(sorted(dump::to_matrix_def(dict)), read("matrix.def"));
+}
+
+#[test]
+fn keeps_all_homonyms() {
+    let tokenizer = load();
+    let features = tokenizer.word_features().unwrap();
+    let bytes = fs::read(Path::new(FIXTURES).join("mini-ipadic/words.csv")).unwrap();
+    let (csv, _, _) = encoding_rs::EUC_JP.decode(&bytes);
+    let mut homonyms: HashMap<&str, Vec<String>> = HashMap::new();
+    for line in csv.lines() {
+        let columns = line.split(',').collect::<Vec<_>>();
+        homonyms
+            .entry(columns[0])
+            .or_default()
+            .push(columns[4..].join(","));
+    }
+    assert!(homonyms.values().any(|rows| rows.len() > 1));
+
+    for (surface, mut expected) in homonyms {
+        let lattice = tokenizer.parse(surface).unwrap();
+        let len = surface.chars().count();
+        let mut actual = lattice.indices[0]
+            .iter()
+            .filter(|(wid, wlen)| matches!(wid, WordIdentifier::Known(_, _)) && *wlen == len)
+            .map(|(wid, _)| features.get(wid).unwrap().join(","))
+            .collect::<Vec<_>>();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected, "homonyms of {}", surface);
+    }
+}
@@ -0,0 +1,32 @@
+#!/bin/sh
+# Regenerates expected.txt and mini-mecab with MeCab from mini-ipadic and corpus.txt,
+# then fails if MeCab gives another result with the dictionary exported by `goya export`.
+# mecab-dict-index is usually not in PATH, so it's looked up with mecab-config.
+set -eu
+cd "$(dirname "$0")"
+dict_index="$(mecab-config --libexecdir)/mecab-dict-index"
+tmp="$(mktemp -d)"
+trap 'rm -rf "$tmp"' EXIT
+
+# MeCab needs dicrc, which mini-ipadic doesn't have. It's the one of IPADIC
+cp -r mini-ipadic "$tmp/src"
+cat > "$tmp/src/dicrc" <<'DICRC'
+cost-factor = 800
+bos-feature = BOS/EOS,*,*,*,*,*,*,*,*
+eval-size = 8
+unk-eval-size = 4
+config-charset = EUC-JP
+DICRC
+mkdir "$tmp/dic"
+"$dict_index" -d "$tmp/src" -o "$tmp/dic" -f euc-jp -t utf-8
+cp "$tmp/src/dicrc" "$tmp/dic/"
+mecab -d "$tmp/dic" -F '%m\t%H\t%pC\n' -E 'EOS\t%pC\n' < corpus.txt > expected.txt
+
+rm -rf mini-mecab
+mkdir mini-mecab
+cp "$tmp/dic/sys.dic" "$tmp/dic/unk.dic" "$tmp/dic/matrix.bin" "$tmp/dic/char.bin" mini-mecab/
+
+# Checks that MeCab reads the dictionary exported by `goya export` and gives the same result
+cargo run -q -p goya-cli -- -d "$tmp/goya" compile mini-ipadic
+cargo run -q -p goya-cli -- -d "$tmp/goya" export "$tmp/exported"
+mecab -d "$tmp/exported" -F '%m\t%H\t%pC\n' -E 'EOS\t%pC\n' < corpus.txt | diff -u expected.txt -
@@ -1,29 +1,34 @@
-use goya::dictionary::Dictionary;
 use goya::dot;
-use goya::double_array::DoubleArray;
 use goya::id::WordIdentifier;
 use goya::lattice::Lattice;
+use goya::offset::Offsets;
+use goya::{Error, Token, Tokenizer};
 use goya_ipadic::ipadic::IPADic;
-use rkyv::{archived_root, Deserialize, Infallible};
+use goya_ipadic::ipadic_loader::IPADicLoader;
 use serde::Serialize;
+use std::sync::RwLock;
 use wasm_bindgen::prelude::*;
 
 #[macro_use]
 extern crate lazy_static;
 
 lazy_static! {
-    static ref DOUBLE_ARRAY: DoubleArray = {
-        let archived =
-            unsafe { archived_root::<DoubleArray>(include_bytes!("../__generated__/da.bin")) };
-        archived.deserialize(&mut Infallible).unwrap()
-    };
-    static ref IPADIC: IPADic = {
-        let archived =
-            unsafe { archived_root::<IPADic>(include_bytes!("../__generated__/dict.bin")) };
-        archived.deserialize(&mut Infallible).unwrap()
-    };
+    // The embedded archives are trusted, so the validation is skipped to start up faster
+    static ref TOKENIZER: RwLock<Tokenizer<IPADic>> = RwLock::new(unsafe {
+        Tokenizer::from_archived_unchecked(
+            DOUBLE_ARRAY,
+            include_bytes!("../__generated__/dict.bin"),
+        )
+    });
 }
 
+// The file is named after the kind of the double array by `goya export`,
+// so the one exported without the same features fails to compile instead of being misread
+#[cfg(not(feature = "utf8-double-array"))]
+const DOUBLE_ARRAY: &[u8] = include_bytes!("../__generated__/da.bin");
+#[cfg(feature = "utf8-double-array")]
+const DOUBLE_ARRAY: &[u8] = include_bytes!("../__generated__/da-utf8.bin");
+
 #[derive(Serialize)]
 pub struct WasmMorpheme {
     wid: WordIdentifier,
@@ -32,8 +37,36 @@ pub struct WasmMorpheme {
     left_context_id: usize,
     right_context_id: usize,
     cost: i16,
+    offsets: Offsets,
+    leading_whitespace: String,
+    /// Only words of the user dictionary have it since goya-features doesn't know them
+    features: Option<Vec<String>>,
+}
+impl WasmMorpheme {
+    fn from(token: Token) -> WasmMorpheme {
+        let features = match token.wid {
+            WordIdentifier::User(_, _) => Some(token.features),
+            _ => None,
+        };
+        WasmMorpheme {
+            wid: token.wid,
+            is_known: token.is_known,
+            surface_form: token.surface,
+            left_context_id: token.left_context_id,
+            right_context_id: token.right_context_id,
+            cost: token.word_cost,
+            offsets: token.offsets,
+            leading_whitespace: token.leading_whitespace,
+            features,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WasmPath {
+    cost: i32,
+    morphemes: Vec<WasmMorpheme>,
 }
-impl WasmMorpheme {}
 
 #[wasm_bindgen]
 pub struct WasmLattice {
@@ -41,56 +74,94 @@ pub struct WasmLattice {
 }
 #[wasm_bindgen]
 impl WasmLattice {
-    pub fn as_dot(&self) -> String {
-        dot::render(&self.lattice, &*IPADIC).unwrap()
+    pub fn as_dot(&self) -> Result<String, JsValue> {
+        let tokenizer = TOKENIZER.read().unwrap();
+        dot::render(&self.lattice, &tokenizer.dictionary()).map_err(to_js_error)
     }
 
-    pub fn wakachi(&self) -> Vec<JsValue> {
-        self.best_morphemes()
+    pub fn wakachi(&self) -> Result<Vec<JsValue>, JsValue> {
+        Ok(self
+            .best_morphemes()?
+            .iter()
             .map(|morpheme| serde_wasm_bindgen::to_value(&morpheme.surface_form).unwrap())
-            .collect()
+            .collect())
     }
 
-    pub fn find_best(&self) -> Vec<JsValue> {
-        self.best_morphemes()
+    pub fn find_best(&self) -> Result<Vec<JsValue>, JsValue> {
+        Ok(self
+            .best_morphemes()?
+            .iter()
             .map(|morpheme| serde_wasm_bindgen::to_value(&morpheme).unwrap())
-            .collect()
+            .collect())
     }
 
-    fn best_morphemes(&self) -> impl Iterator<Item = WasmMorpheme> + '_ {
+    pub fn nbest(&self, k: usize) -> Result<Vec<JsValue>, JsValue> {
+        let tokenizer = TOKENIZER.read().unwrap();
         self.lattice
-            .find_best()
-            .map(|path| {
-                path.into_iter().map(|wid| {
-                    let morpheme = IPADIC.get(&wid).unwrap();
-                    let (surface_form, is_known) = match &wid {
-                        WordIdentifier::Known(_, s) => (s.to_string(), true),
-                        WordIdentifier::Unknown(_, s) => (s.to_string(), false),
-                    };
-                    WasmMorpheme {
-                        wid,
-                        is_known,
-                        surface_form,
-                        left_context_id: morpheme.left_context_id,
-                        right_context_id: morpheme.right_context_id,
-                        cost: morpheme.cost,
-                    }
-                })
+            .nbest_paths(&tokenizer.dictionary(), k)
+            .map_err(to_js_error)?
+            .into_iter()
+            .map(|(path, cost)| {
+                let morphemes = tokenizer
+                    .tokens(&self.lattice, &path)
+                    .map_err(to_js_error)?
+                    .into_iter()
+                    .map(WasmMorpheme::from)
+                    .collect();
+                Ok(serde_wasm_bindgen::to_value(&WasmPath { cost, morphemes }).unwrap())
             })
+            .collect()
+    }
+
+    pub fn trailing_whitespace(&self) -> String {
+        self.lattice.trailing_whitespace().to_string()
+    }
+
+    fn best_morphemes(&self) -> Result<Vec<WasmMorpheme>, JsValue> {
+        let path = self
+            .lattice
+            .find_best_path()
+            .ok_or(Error::PathNotFound)
+            .map_err(to_js_error)?;
+        Ok(TOKENIZER
+            .read()
             .unwrap()
+            .tokens(&self.lattice, &path)
+            .map_err(to_js_error)?
+            .into_iter()
+            .map(WasmMorpheme::from)
+            .collect())
     }
 }
 
+fn to_js_error<E: std::fmt::Display>(err: E) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
 #[wasm_bindgen]
 pub async fn ready() {
-    futures::join!(async { lazy_static::initialize(&IPADIC) }, async {
-        lazy_static::initialize(&DOUBLE_ARRAY)
-    });
+    lazy_static::initialize(&TOKENIZER);
 }
 
 #[wasm_bindgen]
-pub fn parse(text: &str) -> WasmLattice {
-    WasmLattice {
-        lattice: Lattice::parse(text, &DOUBLE_ARRAY, &*IPADIC),
-    }
+pub fn parse(text: &str) -> Result<WasmLattice, JsValue> {
+    Ok(WasmLattice {
+        lattice: TOKENIZER.read().unwrap().parse(text).map_err(to_js_error)?,
+    })
+}
+
+/// Replaces the user dictionary with the CSV in the same format as the IPADIC CSVs. An empty CSV removes it
+#[wasm_bindgen]
+pub fn load_user_dictionary(csv: &str) -> Result<(), JsValue> {
+    let user = if csv.trim().is_empty() {
+        None
+    } else {
+        Some(
+            IPADicLoader {}
+                .parse_user_dictionary(csv)
+                .map_err(to_js_error)?,
+        )
+    };
+    TOKENIZER.write().unwrap().set_user_dictionary(user);
+    Ok(())
 }
@@ -12,16 +12,15 @@ lazy_static! {
 
 #[wasm_bindgen]
 pub fn get_features(wids: &JsValue) -> JsValue {
-    let wids: Vec<WordIdentifier> = wids.into_serde().unwrap();
+    let wids: Vec<WordIdentifier> = serde_wasm_bindgen::from_value(wids.clone()).unwrap();
     let features: Vec<Vec<String>> = wids
         .iter()
         .map(|wid| {
+            // Words of the user dictionary don't have features here
             WORD_FEATURES
                 .get(wid)
-                .unwrap()
-                .iter()
-                .map(|s| s.to_string())
-                .collect()
+                .map(|features| features.iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default()
         })
         .collect::<Vec<_>>();
     serde_wasm_bindgen::to_value(&features).unwrap()